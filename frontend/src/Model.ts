@@ -5,6 +5,7 @@ export interface Track {
     file_path: string | null
     artist_id: string
     artist_info: ArtistInfo
+    artists: ArtistCredit[]
     album_id: string
     album_info: AlbumInfo
 }
@@ -19,6 +20,10 @@ export interface TrackInfo {
     title: string
     isrc: string | null
     duration_secs: number
+    track_number: number | null
+    disc_number: number | null
+    genre: string | null
+    composer: string | null
 }
 
 export interface ArtistInfo {
@@ -26,8 +31,18 @@ export interface ArtistInfo {
     image_url: string
 }
 
+export type ArtistRole = "primary" | "featured" | "remixer" | "composer"
+
+export interface ArtistCredit {
+    artist_id: string
+    artist_info: ArtistInfo
+    role: ArtistRole
+}
+
 export interface AlbumInfo {
     title: string
     cover_image_url: string
     release_date: string | null
+    album_artist: string | null
+    year: number | null
 }
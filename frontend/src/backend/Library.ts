@@ -18,12 +18,46 @@ export class Library {
                     this.libraryTrackIds.add(e.args.track_id)
                 }
                 return
-            case "TrackAddedToPlaylist":
+            case "PlaylistCreated":
+                if (this.playlists !== null) {
+                    this.playlists.push({ id: e.args.playlist_id, name: e.args.name })
+                }
+                return
+            case "PlaylistRenamed": {
+                const playlist = (this.playlists || []).find(p => p.id === e.args.playlist_id)
+                if (playlist !== undefined) {
+                    playlist.name = e.args.name
+                }
+                return
+            }
+            case "PlaylistDeleted":
+                if (this.playlists !== null) {
+                    this.playlists = this.playlists.filter(p => p.id !== e.args.playlist_id)
+                }
+                this.trackIdsByPlaylistId.delete(e.args.playlist_id)
+                return
+            case "TrackAddedToPlaylist": {
+                const trackIds = this.trackIdsByPlaylistId.get(e.args.playlist_id)
+                if (trackIds !== undefined) {
+                    trackIds.splice(e.args.index, 0, e.args.track_id)
+                }
+                return
+            }
+            case "PlaylistTrackMoved": {
                 const trackIds = this.trackIdsByPlaylistId.get(e.args.playlist_id)
                 if (trackIds !== undefined) {
-                    trackIds.push(e.args.track_id)
+                    const [trackId] = trackIds.splice(e.args.from, 1)
+                    trackIds.splice(e.args.to, 0, trackId)
                 }
                 return
+            }
+            case "TrackRemovedFromPlaylist": {
+                const trackIds = this.trackIdsByPlaylistId.get(e.args.playlist_id)
+                if (trackIds !== undefined) {
+                    trackIds.splice(e.args.index, 1)
+                }
+                return
+            }
         }
     }
 
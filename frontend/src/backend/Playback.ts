@@ -1,6 +1,6 @@
 import { observable, action, computed } from "mobx"
 import { PlaybackTiming } from "../Model"
-import { ServerApi, ServerEvent, CurrentTrack } from "./ServerApi"
+import { ServerApi, ServerEvent, CurrentTrack, RepeatMode } from "./ServerApi"
 
 export class Playback {
     constructor(private serverApi: ServerApi) {
@@ -22,9 +22,13 @@ export class Playback {
 
     private initialiseState = async () => {
         // TODO initialise all the state
-        const { volume, muted, current_track, paused } = await this.serverApi.request("GetPlaybackState")
+        const { volume, muted, current_track, paused, shuffled, repeat } = await this.serverApi.request(
+            "GetPlaybackState",
+        )
         this.volume = volume
         this.muted = muted
+        this.shuffled = shuffled
+        this.repeat = repeat
         this.updateCurrentTrack(current_track, paused)
     }
 
@@ -38,6 +42,8 @@ export class Playback {
                 this.muted = e.args.muted
                 return
             case "PlaybackChanged":
+                this.shuffled = e.args.shuffled
+                this.repeat = e.args.repeat
                 this.updateCurrentTrack(e.args.current_track, e.args.paused)
                 return
         }
@@ -45,6 +51,8 @@ export class Playback {
 
     @observable volume = 0.5
     @observable muted = false
+    @observable shuffled = false
+    @observable repeat: RepeatMode = "Off"
 
     @observable
     currentTrack: PlaybackTiming & { trackId: string } | null = null
@@ -62,6 +70,14 @@ export class Playback {
 
     skipToNext = () => this.serverApi.request("SkipToNext")
 
+    skipToPrevious = () => this.serverApi.request("SkipToPrevious")
+
+    toggleShuffle = () => this.serverApi.request("ToggleShuffle")
+
+    toggleRepeatOne = () => this.serverApi.request("ToggleRepeatOne")
+
+    toggleRepeatAll = () => this.serverApi.request("ToggleRepeatAll")
+
     stop = () => this.serverApi.request("Stop")
 
     enqueue = (trackId: string) => this.serverApi.request("Enqueue", { track_id: trackId })
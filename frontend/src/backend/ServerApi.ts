@@ -1,28 +1,57 @@
 import { RPCWebSocket, Payload } from "../websocket"
-import { Track, AlbumInfo, ArtistInfo, TrackInfo } from "../Model"
+import { Track, AlbumInfo, ArtistInfo, ArtistRole, TrackInfo } from "../Model"
 
 interface ServerRPCApi {
     (type: "Enqueue", args: { track_id: string }): Promise<void>
+    (type: "EnqueueNext", args: { track_id: string }): Promise<void>
+    (type: "EnqueueAlbum", args: { album_id: string; mode: EnqueueMode }): Promise<void>
+    (type: "EnqueuePlaylist", args: { playlist_id: string; mode: EnqueueMode }): Promise<void>
+    (type: "EnqueueArtist", args: { artist_id: string; mode: EnqueueMode }): Promise<void>
+    (type: "GetQueue"): Promise<{ tracks: EnqueuedTrack[] }>
+    (type: "RemoveFromQueue", args: { entry_marker: string }): Promise<void>
+    (type: "MoveInQueue", args: { entry_marker: string; index: number }): Promise<void>
+    (type: "JumpTo", args: { entry_marker: string }): Promise<void>
     (type: "Stop"): Promise<void>
     (type: "Pause"): Promise<void>
     (type: "Unpause"): Promise<void>
     (type: "SkipToNext"): Promise<void>
+    (type: "SkipToPrevious"): Promise<void>
+    (type: "GetHistory"): Promise<{ tracks: EnqueuedTrack[] }>
+    (type: "ToggleShuffle"): Promise<void>
+    (type: "ToggleRepeatOne"): Promise<void>
+    (type: "ToggleRepeatAll"): Promise<void>
     (type: "ChangeVolume", args: { muted?: boolean; volume?: number }): Promise<void>
     (type: "CompleteFilePath", args: { prefix: string }): Promise<void>
     (type: "GetTracks", args: { track_ids: string[] }): Promise<Record<string, Track | null>>
     (type: "GetLibrary"): Promise<{ tracks: Track[] }>
     (type: "AddToLibrary", args: { path: string }): Promise<void>
     (type: "GetPlaybackState"): Promise<PlaybackState>
-    (type: "ListPlaylists"): Promise<{ playlists: { id: string; name: string }[] }>
-    (type: "GetPlaylist", args: { id: string }): Promise<{ name: string; track_ids: string[] } | null>
-    (type: "AddTrackToPlaylist", args: { track_id: string; playlist_id: string }): Promise<void>
+    (type: "GetAlbumTracks", args: { album_id: string }): Promise<Track[]>
+    (type: "ListArtists"): Promise<[string, ArtistInfo, ArtistRole[]][]>
+    (type: "ListPlaylists"): Promise<{ playlists: PlaylistSummary[] }>
+    (type: "GetPlaylist", args: { id: string }): Promise<(PlaylistSummary & { track_ids: string[] }) | null>
+    (type: "CreatePlaylist", args: { name: string }): Promise<string>
+    (type: "RenamePlaylist", args: { id: string; name: string }): Promise<void>
+    (type: "DeletePlaylist", args: { id: string }): Promise<void>
+    (type: "AddTrackToPlaylist", args: { track_id: string; playlist_id: string; index?: number }): Promise<void>
+    (type: "MovePlaylistTrack", args: { playlist_id: string; from: number; to: number }): Promise<void>
+    (type: "RemoveFromPlaylist", args: { playlist_id: string; index: number }): Promise<void>
     (type: "Search", args: { query: string }): Promise<SearchResults>
 }
 
+export interface PlaylistSummary {
+    id: string
+    name: string
+    track_count: number
+    duration_secs: number
+}
+
 export interface PlaybackState {
     muted: boolean
     volume: number
     paused: boolean
+    shuffled: boolean
+    repeat: RepeatMode
     current_track: CurrentTrack | null
 }
 
@@ -30,11 +59,20 @@ export interface SearchResults {
     tracks: TrackSearchResult[]
     albums: SearchResult<AlbumInfo>[]
     artists: SearchResult<ArtistInfo>[]
+    /** How the search went on each service, by service ID */
+    services: { [service: string]: ServiceSearch }
+}
+
+export interface ServiceSearch {
+    found: number
+    /** Why the service couldn't be searched, if it couldn't */
+    error: string | null
 }
 
 export interface SearchResult<T> {
-    library_id: string
-    external_id: string
+    library_id: string | null
+    /** The IDs on each service that found the result */
+    external_ids: string[]
     info: T
 }
 
@@ -46,6 +84,10 @@ export interface TrackSearchResult {
 
 type ServerEventHandler = (e: ServerEvent) => void
 
+export type EnqueueMode = "Replace" | "Next" | "Last"
+
+export type RepeatMode = "Off" | "One" | "All"
+
 export interface EnqueuedTrack {
     id: string
     duration_secs: number
@@ -59,9 +101,18 @@ export interface CurrentTrack {
 
 export type ServerEvent =
     | { type: "VolumeChanged"; args: { muted: boolean; volume: number } }
-    | { type: "PlaybackChanged"; args: { paused: boolean; current_track: CurrentTrack | null } }
+    | {
+          type: "PlaybackChanged"
+          args: { paused: boolean; shuffled: boolean; repeat: RepeatMode; current_track: CurrentTrack | null }
+      }
+    | { type: "QueueChanged"; args: { tracks: EnqueuedTrack[] } }
     | { type: "TrackAddedToLibrary"; args: Track }
-    | { type: "TrackAddedToPlaylist"; args: { track_id: string; playlist_id: string } }
+    | { type: "PlaylistCreated"; args: { playlist_id: string; name: string } }
+    | { type: "PlaylistRenamed"; args: { playlist_id: string; name: string } }
+    | { type: "PlaylistDeleted"; args: { playlist_id: string } }
+    | { type: "TrackAddedToPlaylist"; args: { track_id: string; playlist_id: string; index: number } }
+    | { type: "PlaylistTrackMoved"; args: { playlist_id: string; from: number; to: number } }
+    | { type: "TrackRemovedFromPlaylist"; args: { playlist_id: string; index: number } }
 
 export class ServerApi {
     private handleEvent = (payload: Payload) => {
@@ -39,7 +39,7 @@ const Tracks = (props: SearchResults) => {
     return (
         <TrackTable
             tracks={props.tracks.map(t => ({
-                id: t.track.external_id,
+                id: t.track.library_id || t.track.external_ids[0] || "",
                 coverImageUrl: t.album.info.cover_image_url,
                 title: t.track.info.title,
                 albumName: t.album.info.title,
@@ -60,7 +60,7 @@ const Artists = (props: { artists: SearchResults["artists"] }) => {
             {iterate(props.artists)
                 .take(24)
                 .map(a => {
-                    const link = `/artists/${a.library_id || a.external_id}`
+                    const link = `/artists/${a.library_id || a.external_ids[0]}`
                     return (
                         <Grid
                             direction="y"
@@ -0,0 +1,16 @@
+CREATE TABLE tracks_without_missing (
+    track_id INTEGER PRIMARY KEY NOT NULL,
+    album_id INTEGER NOT NULL REFERENCES albums (album_id),
+    artist_id INTEGER NOT NULL REFERENCES artists (artist_id),
+    title TEXT NOT NULL,
+    isrc TEXT,
+    duration_secs REAL NOT NULL,
+    file_path TEXT
+);
+
+INSERT INTO tracks_without_missing
+SELECT track_id, album_id, artist_id, title, isrc, duration_secs, file_path FROM tracks;
+
+DROP TABLE tracks;
+
+ALTER TABLE tracks_without_missing RENAME TO tracks;
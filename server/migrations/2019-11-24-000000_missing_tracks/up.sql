@@ -0,0 +1 @@
+ALTER TABLE tracks ADD COLUMN missing BOOLEAN NOT NULL DEFAULT 0;
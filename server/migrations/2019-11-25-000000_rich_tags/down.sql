@@ -0,0 +1,31 @@
+CREATE TABLE tracks_without_tags (
+    track_id INTEGER PRIMARY KEY NOT NULL,
+    album_id INTEGER NOT NULL REFERENCES albums (album_id),
+    artist_id INTEGER NOT NULL REFERENCES artists (artist_id),
+    title TEXT NOT NULL,
+    isrc TEXT,
+    duration_secs REAL NOT NULL,
+    file_path TEXT,
+    missing BOOLEAN NOT NULL DEFAULT 0
+);
+
+INSERT INTO tracks_without_tags
+SELECT track_id, album_id, artist_id, title, isrc, duration_secs, file_path, missing FROM tracks;
+
+DROP TABLE tracks;
+
+ALTER TABLE tracks_without_tags RENAME TO tracks;
+
+CREATE TABLE albums_without_tags (
+    album_id INTEGER PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    cover_image_url TEXT,
+    release_date TEXT
+);
+
+INSERT INTO albums_without_tags
+SELECT album_id, title, cover_image_url, release_date FROM albums;
+
+DROP TABLE albums;
+
+ALTER TABLE albums_without_tags RENAME TO albums;
@@ -0,0 +1,7 @@
+ALTER TABLE tracks ADD COLUMN track_number INTEGER;
+ALTER TABLE tracks ADD COLUMN disc_number INTEGER;
+ALTER TABLE tracks ADD COLUMN genre TEXT;
+ALTER TABLE tracks ADD COLUMN composer TEXT;
+
+ALTER TABLE albums ADD COLUMN album_artist TEXT;
+ALTER TABLE albums ADD COLUMN year INTEGER;
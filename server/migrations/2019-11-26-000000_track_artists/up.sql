@@ -0,0 +1,10 @@
+CREATE TABLE track_artists (
+    _id INTEGER PRIMARY KEY NOT NULL,
+    track_id INTEGER NOT NULL REFERENCES tracks (track_id),
+    artist_id INTEGER NOT NULL REFERENCES artists (artist_id),
+    role TEXT NOT NULL,
+    position INTEGER NOT NULL
+);
+
+INSERT INTO track_artists (track_id, artist_id, role, position)
+SELECT track_id, artist_id, 'primary', 0 FROM tracks;
@@ -0,0 +1,18 @@
+DROP INDEX albums_by_musicbrainz_id;
+DROP INDEX albums_by_title;
+
+CREATE TABLE albums_without_musicbrainz_id (
+    album_id INTEGER PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    cover_image_url TEXT,
+    release_date TEXT,
+    album_artist TEXT,
+    year INTEGER
+);
+
+INSERT INTO albums_without_musicbrainz_id
+SELECT album_id, title, cover_image_url, release_date, album_artist, year FROM albums;
+
+DROP TABLE albums;
+
+ALTER TABLE albums_without_musicbrainz_id RENAME TO albums;
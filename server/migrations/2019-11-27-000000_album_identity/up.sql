@@ -0,0 +1,14 @@
+ALTER TABLE albums ADD COLUMN musicbrainz_id TEXT;
+
+CREATE INDEX albums_by_title ON albums (title);
+CREATE INDEX albums_by_musicbrainz_id ON albums (musicbrainz_id);
+
+-- local albums are now told apart by who they're by, so they all need an album artist
+UPDATE albums SET album_artist = (
+    SELECT artists.name FROM tracks
+    INNER JOIN artists ON artists.artist_id = tracks.artist_id
+    WHERE tracks.album_id = albums.album_id AND tracks.file_path IS NOT NULL
+    ORDER BY tracks.track_id
+    LIMIT 1
+)
+WHERE album_artist IS NULL;
@@ -0,0 +1,18 @@
+DROP TRIGGER artists_search_delete;
+DROP TRIGGER artists_search_update;
+DROP TRIGGER artists_search_insert;
+DROP TRIGGER albums_search_delete;
+DROP TRIGGER albums_search_update;
+DROP TRIGGER albums_search_insert;
+DROP TRIGGER track_artists_search_delete;
+DROP TRIGGER track_artists_search_update;
+DROP TRIGGER track_artists_search_insert;
+DROP TRIGGER tracks_search_delete;
+DROP TRIGGER tracks_search_update;
+DROP TRIGGER tracks_search_insert;
+
+DROP VIEW track_artist_names;
+
+DROP TABLE artists_search;
+DROP TABLE albums_search;
+DROP TABLE tracks_search;
@@ -0,0 +1,119 @@
+-- full text indexes for searching the library, where the rowid is the ID of the indexed row and
+-- tracks can be found by their artists and album as well as their title
+CREATE VIRTUAL TABLE tracks_search USING fts5 (
+    title,
+    artist,
+    album,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+CREATE VIRTUAL TABLE albums_search USING fts5 (
+    title,
+    album_artist,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+CREATE VIRTUAL TABLE artists_search USING fts5 (
+    name,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+-- every artist credited on a track, falling back to its primary artist while it has no credits,
+-- which is the case between inserting a track and inserting its credits
+CREATE VIEW track_artist_names (track_id, names) AS
+SELECT tracks.track_id, coalesce(
+    (
+        SELECT group_concat(credited.name, ' ') FROM track_artists
+        INNER JOIN artists AS credited ON credited.artist_id = track_artists.artist_id
+        WHERE track_artists.track_id = tracks.track_id
+    ),
+    artists.name
+) FROM tracks
+INNER JOIN artists ON artists.artist_id = tracks.artist_id;
+
+INSERT INTO tracks_search (rowid, title, artist, album)
+SELECT tracks.track_id, tracks.title, track_artist_names.names, albums.title FROM tracks
+INNER JOIN track_artist_names ON track_artist_names.track_id = tracks.track_id
+INNER JOIN albums ON albums.album_id = tracks.album_id;
+
+INSERT INTO albums_search (rowid, title, album_artist)
+SELECT album_id, title, album_artist FROM albums;
+
+INSERT INTO artists_search (rowid, name)
+SELECT artist_id, name FROM artists;
+
+CREATE TRIGGER tracks_search_insert AFTER INSERT ON tracks BEGIN
+    INSERT INTO tracks_search (rowid, title, artist, album) VALUES (
+        new.track_id,
+        new.title,
+        (SELECT names FROM track_artist_names WHERE track_id = new.track_id),
+        (SELECT title FROM albums WHERE album_id = new.album_id)
+    );
+END;
+
+CREATE TRIGGER tracks_search_update AFTER UPDATE ON tracks BEGIN
+    UPDATE tracks_search SET
+        title = new.title,
+        artist = (SELECT names FROM track_artist_names WHERE track_id = new.track_id),
+        album = (SELECT title FROM albums WHERE album_id = new.album_id)
+    WHERE rowid = new.track_id;
+END;
+
+CREATE TRIGGER tracks_search_delete AFTER DELETE ON tracks BEGIN
+    DELETE FROM tracks_search WHERE rowid = old.track_id;
+END;
+
+CREATE TRIGGER track_artists_search_insert AFTER INSERT ON track_artists BEGIN
+    UPDATE tracks_search
+    SET artist = (SELECT names FROM track_artist_names WHERE track_id = new.track_id)
+    WHERE rowid = new.track_id;
+END;
+
+CREATE TRIGGER track_artists_search_update AFTER UPDATE ON track_artists BEGIN
+    UPDATE tracks_search
+    SET artist = (SELECT names FROM track_artist_names WHERE track_id = tracks_search.rowid)
+    WHERE rowid IN (old.track_id, new.track_id);
+END;
+
+CREATE TRIGGER track_artists_search_delete AFTER DELETE ON track_artists BEGIN
+    UPDATE tracks_search
+    SET artist = (SELECT names FROM track_artist_names WHERE track_id = old.track_id)
+    WHERE rowid = old.track_id;
+END;
+
+CREATE TRIGGER albums_search_insert AFTER INSERT ON albums BEGIN
+    INSERT INTO albums_search (rowid, title, album_artist)
+    VALUES (new.album_id, new.title, new.album_artist);
+END;
+
+CREATE TRIGGER albums_search_update AFTER UPDATE ON albums BEGIN
+    UPDATE albums_search SET title = new.title, album_artist = new.album_artist
+    WHERE rowid = new.album_id;
+    UPDATE tracks_search SET album = new.title
+    WHERE rowid IN (SELECT track_id FROM tracks WHERE album_id = new.album_id);
+END;
+
+CREATE TRIGGER albums_search_delete AFTER DELETE ON albums BEGIN
+    DELETE FROM albums_search WHERE rowid = old.album_id;
+END;
+
+CREATE TRIGGER artists_search_insert AFTER INSERT ON artists BEGIN
+    INSERT INTO artists_search (rowid, name) VALUES (new.artist_id, new.name);
+END;
+
+CREATE TRIGGER artists_search_update AFTER UPDATE ON artists BEGIN
+    UPDATE artists_search SET name = new.name WHERE rowid = new.artist_id;
+    UPDATE tracks_search
+    SET artist = (SELECT names FROM track_artist_names WHERE track_id = tracks_search.rowid)
+    WHERE rowid IN (
+        SELECT track_id FROM tracks WHERE artist_id = new.artist_id
+        UNION SELECT track_id FROM track_artists WHERE artist_id = new.artist_id
+    );
+END;
+
+CREATE TRIGGER artists_search_delete AFTER DELETE ON artists BEGIN
+    DELETE FROM artists_search WHERE rowid = old.artist_id;
+END;
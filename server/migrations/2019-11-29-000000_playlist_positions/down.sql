@@ -0,0 +1,15 @@
+DROP INDEX playlist_tracks_by_position;
+
+CREATE TABLE playlist_tracks_without_position (
+    _id INTEGER PRIMARY KEY NOT NULL,
+    playlist_id INTEGER NOT NULL REFERENCES playlists (playlist_id),
+    track_id INTEGER NOT NULL REFERENCES tracks (track_id)
+);
+
+-- the order of the rows is the only order there will be
+INSERT INTO playlist_tracks_without_position (playlist_id, track_id)
+SELECT playlist_id, track_id FROM playlist_tracks ORDER BY playlist_id, position;
+
+DROP TABLE playlist_tracks;
+
+ALTER TABLE playlist_tracks_without_position RENAME TO playlist_tracks;
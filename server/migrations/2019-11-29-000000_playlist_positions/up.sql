@@ -0,0 +1,9 @@
+ALTER TABLE playlist_tracks ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+
+-- keep the order tracks were added in, which is all the order there was
+UPDATE playlist_tracks SET position = (
+    SELECT COUNT(*) FROM playlist_tracks AS earlier
+    WHERE earlier.playlist_id = playlist_tracks.playlist_id AND earlier._id < playlist_tracks._id
+);
+
+CREATE INDEX playlist_tracks_by_position ON playlist_tracks (playlist_id, position);
@@ -2,11 +2,14 @@ pub mod search;
 
 use crate::errors::Try;
 use crate::file_completions::complete_file_path;
+use crate::file_formats;
 use crate::ids::{ExternalId, Id, LibraryId, Playlist, Track};
-use crate::library::{Library, TrackSummary};
-use crate::model::LoadedTrack;
+use crate::library::{scan_directory, Library, TrackSummary};
+use crate::model::{ArtistRole, LoadedTrack};
 use crate::player::PlayerApp;
-use crate::queue::CurrentTrack;
+use crate::queue::{
+    CrossfadeCurve, CurrentTrack, EnqueueMode, EnqueuedTrack, EntryMarker, RepeatMode,
+};
 use crate::services::{ExternalTrack, Service, ServiceId};
 use anyhow::Context;
 use fstrings::{f, format_args_f};
@@ -15,11 +18,14 @@ use serde_derive::{Deserialize, Serialize};
 use slotmap::{DenseSlotMap, Key};
 use std::collections::{BTreeMap, HashMap};
 use std::convert::Into;
-use std::fs;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for each service when searching.
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct App {
-    pub services: HashMap<ServiceId, Box<dyn Service>>,
+    pub services: HashMap<ServiceId, Arc<dyn Service>>,
     pub player: PlayerApp,
     pub library: Library,
     // TODO: do we need this here?
@@ -33,14 +39,61 @@ pub enum Request {
     Enqueue {
         track_id: String,
     },
+    /// Adds a track after the current one
+    EnqueueNext {
+        track_id: String,
+    },
+    /// Adds the tracks of an album in order
+    EnqueueAlbum {
+        album_id: String,
+        mode: EnqueueMode,
+    },
+    EnqueuePlaylist {
+        playlist_id: String,
+        mode: EnqueueMode,
+    },
+    /// Adds every track an artist is a primary artist on, album by album
+    EnqueueArtist {
+        artist_id: String,
+        mode: EnqueueMode,
+    },
+    GetQueue,
+    RemoveFromQueue {
+        entry_marker: EntryMarker,
+    },
+    MoveInQueue {
+        entry_marker: EntryMarker,
+        index: usize,
+    },
+    /// Skips ahead or back to an entry in the queue
+    JumpTo {
+        entry_marker: EntryMarker,
+    },
     Stop,
     Pause,
     Unpause,
     SkipToNext,
+    /// Restarts the current track, or goes back to the previous one if it only just started
+    SkipToPrevious,
+    /// Lists the tracks played recently, most recent first
+    GetHistory,
+    /// Shuffles the tracks after the current one, or puts them back in order
+    ToggleShuffle,
+    /// Repeats the current track, or turns repeat off if it's already being repeated
+    ToggleRepeatOne,
+    /// Repeats the whole queue, or turns repeat off if it's already being repeated
+    ToggleRepeatAll,
+    Seek {
+        position_secs: f32,
+    },
     ChangeVolume {
         volume: Option<f32>,
         muted: Option<bool>,
     },
+    ChangeCrossfade {
+        duration_secs: Option<f32>,
+        curve: Option<CrossfadeCurve>,
+    },
     CompleteFilePath {
         prefix: String,
     },
@@ -51,15 +104,43 @@ pub enum Request {
     AddToLibrary {
         path: String,
     },
+    ScanDirectory {
+        path: String,
+        recursive: bool,
+    },
     ListAlbums,
+    GetAlbumTracks {
+        album_id: String,
+    },
     ListArtists,
     ListPlaylists,
     GetPlaylist {
         id: String,
     },
+    CreatePlaylist {
+        name: String,
+    },
+    RenamePlaylist {
+        id: String,
+        name: String,
+    },
+    DeletePlaylist {
+        id: String,
+    },
+    /// Inserts a track before the entry at the index, or at the end if there's no index
     AddTrackToPlaylist {
         track_id: String,
         playlist_id: String,
+        index: Option<usize>,
+    },
+    MovePlaylistTrack {
+        playlist_id: String,
+        from: usize,
+        to: usize,
+    },
+    RemoveFromPlaylist {
+        playlist_id: String,
+        index: usize,
     },
     Search {
         query: String,
@@ -73,26 +154,123 @@ impl App {
         match request {
             GetPlaybackState => self.get_playback_state(),
             Enqueue { track_id } => self.enqueue(track_id),
+            EnqueueNext { track_id } => self.enqueue_next(track_id),
+            EnqueueAlbum { album_id, mode } => {
+                let tracks = self
+                    .library
+                    .album_tracks(album_id.parse()?)
+                    .context("failed to get album tracks")?;
+                self.enqueue_all(tracks.into_iter().map(|t| t.track_id), *mode)
+            }
+            EnqueuePlaylist { playlist_id, mode } => {
+                let playlist = self
+                    .library
+                    .get_playlist(playlist_id.parse()?)
+                    .context("failed to get playlist")?
+                    .ok_or_else(|| anyhow!("no playlist {}", playlist_id))?;
+                self.enqueue_all(playlist.tracks(), *mode)
+            }
+            EnqueueArtist { artist_id, mode } => {
+                let track_ids = self
+                    .library
+                    .artist_track_ids(artist_id.parse()?)
+                    .context("failed to get artist tracks")?;
+                self.enqueue_all(track_ids.into_iter(), *mode)
+            }
+            GetQueue => ok(&QueueListing {
+                tracks: self.player.queue(),
+            }),
+            RemoveFromQueue { entry_marker } => {
+                in_queue(self.player.remove_from_queue(*entry_marker), entry_marker)
+            }
+            MoveInQueue {
+                entry_marker,
+                index,
+            } => in_queue(
+                self.player.move_in_queue(*entry_marker, *index),
+                entry_marker,
+            ),
+            JumpTo { entry_marker } => in_queue(self.player.jump_to(*entry_marker), entry_marker),
             Stop => self.player.empty_queue().and_done(),
             Pause => self.player.pause().and_done(),
             Unpause => self.player.unpause().and_done(),
             SkipToNext => self.player.skip_to_next().and_done(),
+            SkipToPrevious => {
+                self.player.skip_to_previous()?;
+                done()
+            }
+            GetHistory => ok(&QueueListing {
+                tracks: self.player.history(),
+            }),
+            ToggleShuffle => self.player.toggle_shuffle().and_done(),
+            ToggleRepeatOne => self.player.toggle_repeat(RepeatMode::One).and_done(),
+            ToggleRepeatAll => self.player.toggle_repeat(RepeatMode::All).and_done(),
+            Seek { position_secs } => {
+                self.player.seek(*position_secs)?;
+                done()
+            }
             ChangeVolume { volume, muted } => self.player.update_volume(*volume, *muted).and_done(),
+            ChangeCrossfade {
+                duration_secs,
+                curve,
+            } => self
+                .player
+                .update_crossfade(*duration_secs, *curve)
+                .and_done(),
             CompleteFilePath { prefix } => self.completions(prefix),
             GetTracks { track_ids } => self.get_tracks(track_ids),
             GetLibrary => self.list_library(),
             AddToLibrary { path } => self.add_to_library(path.clone()),
+            ScanDirectory { path, recursive } => ok(&scan_directory(
+                &self.library,
+                &self.event_sink,
+                path,
+                *recursive,
+            )?),
             ListAlbums => self.list_albums(),
+            GetAlbumTracks { album_id } => ok(&self
+                .library
+                .album_tracks(album_id.parse()?)
+                .context("failed to get album tracks")?),
             ListArtists => self.list_artists(),
             ListPlaylists => self.list_playlists(),
             GetPlaylist { ref id } => ok(&self
                 .library
                 .get_playlist(id.parse()?)
                 .context("failed to get playlist")?),
+            CreatePlaylist { name } => ok(&self
+                .library
+                .create_playlist(name.clone())
+                .context("failed to create playlist")?),
+            RenamePlaylist { id, name } => self
+                .library
+                .rename_playlist(id.parse()?, name.clone())
+                .context("failed to rename playlist")?
+                .and_done(),
+            DeletePlaylist { id } => self
+                .library
+                .delete_playlist(id.parse()?)
+                .context("failed to delete playlist")?
+                .and_done(),
             AddTrackToPlaylist {
                 track_id,
                 playlist_id,
-            } => self.add_track_to_playlist(track_id, playlist_id),
+                index,
+            } => self.add_track_to_playlist(track_id, playlist_id, *index),
+            MovePlaylistTrack {
+                playlist_id,
+                from,
+                to,
+            } => self
+                .library
+                .move_playlist_track(playlist_id.parse()?, *from, *to)
+                .context("failed to move playlist track")?
+                .and_done(),
+            RemoveFromPlaylist { playlist_id, index } => self
+                .library
+                .remove_from_playlist(playlist_id.parse()?, *index)
+                .context("failed to remove track from playlist")?
+                .and_done(),
             Search { ref query } => self.search(query),
         }
     }
@@ -104,6 +282,41 @@ impl App {
         done()
     }
 
+    fn enqueue_next(&self, track_id: &str) -> Response {
+        let track_id = track_id.parse()?;
+        let track = self.load_track(&track_id)?;
+        self.player.add_next_in_queue(track_id, track)?;
+        done()
+    }
+
+    /// Adds library tracks to the queue all at once. If any of them can't be played, none of them
+    /// are added and the queue is left as it was.
+    fn enqueue_all(
+        &self,
+        track_ids: impl Iterator<Item = LibraryId<Track>>,
+        mode: EnqueueMode,
+    ) -> Response {
+        let mut tracks = Vec::new();
+        let mut failed = Vec::new();
+        for track_id in track_ids.map(Id::Library) {
+            match self.load_track(&track_id) {
+                Ok(track) => tracks.push((track_id, track)),
+                Err(e) => {
+                    log::warn!("can't enqueue track {}: {}", track_id, e);
+                    failed.push(track_id.to_string());
+                }
+            }
+        }
+        if !failed.is_empty() {
+            return Err(anyhow!(
+                "nothing was enqueued, these tracks can't be played: {}",
+                failed.join(", ")
+            ));
+        }
+        self.player.add_all_to_queue(tracks, mode)?;
+        done()
+    }
+
     fn load_track(&self, track_id: &Id<Track>) -> Try<LoadedTrack> {
         match track_id {
             Id::Library(lib_track_id) => {
@@ -111,18 +324,24 @@ impl App {
                     .library
                     .get_track(*lib_track_id)?
                     .ok_or_else(|| anyhow!("Unknown track {}", track_id))?;
+                if track.missing {
+                    return Err(anyhow!("the file for track {} is missing", track_id));
+                }
                 if let Some(file_path) = track.track_info.file_path {
-                    log::info!("loading track {} from {}", track_id, file_path);
+                    log::info!("track {} will be loaded from {}", track_id, file_path);
                     Ok(LoadedTrack {
-                        data: fs::read(&file_path)
-                            .with_context(|| f!("failed to load track file {}", file_path))?,
+                        open: file_formats::open_lazily(file_path),
                         duration_secs: track.track_info.duration_secs,
+                        album_id: Some(track.album_id),
                     })
                 } else {
                     for ext_id in track.external_ids {
                         if let Some(service) = self.services.get(&ext_id.service) {
                             log::info!("fetching track {} from {}", track_id, ext_id);
-                            return service.fetch(&ext_id.id);
+                            return Ok(LoadedTrack {
+                                album_id: Some(track.album_id),
+                                ..service.fetch(&ext_id.id)?
+                            });
                         }
                     }
                     Err(anyhow!(
@@ -192,6 +411,8 @@ impl App {
         struct PlaylistInfo {
             id: LibraryId<Playlist>,
             name: String,
+            track_count: usize,
+            duration_secs: f32,
         }
         ok(&Playlists {
             playlists: self
@@ -201,23 +422,32 @@ impl App {
                 .map(|p| PlaylistInfo {
                     id: p.id,
                     name: p.name,
+                    track_count: p.track_count,
+                    duration_secs: p.duration_secs,
                 })
                 .collect(),
         })
     }
 
-    fn add_track_to_playlist(&self, track_id: &str, playlist_id: &str) -> Response {
+    fn add_track_to_playlist(
+        &self,
+        track_id: &str,
+        playlist_id: &str,
+        index: Option<usize>,
+    ) -> Response {
         let track_id: Id<Track> = track_id.parse()?;
         let playlist_id: LibraryId<Playlist> = playlist_id.parse()?;
         match track_id {
-            Id::Library(track_id) => self.add_library_track_to_playlist(track_id, playlist_id)?,
+            Id::Library(track_id) => {
+                self.add_library_track_to_playlist(track_id, playlist_id, index)?
+            }
             Id::External(track_id) => {
                 // verify that the playlist exists first
                 if !self.library.playlist_exists(playlist_id)? {
                     Err(anyhow!("non existent playlist {}", playlist_id.0))?
                 }
                 let track_id = self.add_external_track_to_library(track_id)?;
-                self.add_library_track_to_playlist(track_id, playlist_id)?;
+                self.add_library_track_to_playlist(track_id, playlist_id, index)?;
             }
         }
         done()
@@ -248,17 +478,22 @@ impl App {
             .map(|a| a.map(|(id, _)| id))
             .transpose()
             .unwrap_or_else(|| self.library.create_artist(artist_info, Some(artist_id)))?;
-        Ok(self
-            .library
-            .create_track(track_info, album_id, artist_id, Some(track_id))?)
+        Ok(self.library.create_track(
+            track_info,
+            album_id,
+            &[(artist_id, ArtistRole::Primary)],
+            Some(track_id),
+        )?)
     }
 
     fn add_library_track_to_playlist(
         &self,
         track_id: LibraryId<Track>,
         playlist_id: LibraryId<Playlist>,
+        index: Option<usize>,
     ) -> Try<()> {
-        self.library.add_track_to_playlist(track_id, playlist_id)
+        self.library
+            .add_track_to_playlist(track_id, playlist_id, index)
     }
 
     fn completions(&self, prefix: &str) -> Response {
@@ -268,16 +503,17 @@ impl App {
     }
 
     fn search(&self, query: &str) -> Response {
-        let service = self
-            .services
-            .values()
-            .next()
-            .ok_or_else(|| anyhow!("No search services registered"))?;
-        let results = service.search(query)?;
-        ok(&self
+        let mut results = self
             .library
-            .resolve(results)
-            .context("failed to resolve search results")?)
+            .search(query)
+            .context("failed to search library")?;
+        results.merge(search::search_services(
+            &self.services,
+            query,
+            SEARCH_TIMEOUT,
+            |service_results| self.library.resolve(service_results),
+        ));
+        ok(&results)
     }
 }
 
@@ -286,6 +522,20 @@ struct LibraryListing {
     tracks: Vec<TrackSummary>,
 }
 
+#[derive(Serialize)]
+struct QueueListing {
+    tracks: Vec<EnqueuedTrack>,
+}
+
+/// Responds to a request about a queue entry, which fails if the entry wasn't in the queue.
+fn in_queue(found: bool, entry_marker: &EntryMarker) -> Response {
+    if found {
+        done()
+    } else {
+        Err(anyhow!("no entry {:?} in the queue", entry_marker).into())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Payload {
     pub json: String,
@@ -334,14 +584,59 @@ pub enum Event {
         muted: bool,
         volume: f32,
     },
+    CrossfadeChanged {
+        duration_secs: f32,
+        curve: CrossfadeCurve,
+    },
     PlaybackChanged {
         paused: bool,
+        shuffled: bool,
+        repeat: RepeatMode,
         current_track: Option<CurrentTrack>,
     },
+    /// Sent after every change to the queue, with everything in it
+    QueueChanged {
+        tracks: Vec<EnqueuedTrack>,
+    },
     TrackAddedToLibrary(TrackSummary),
+    /// A track's metadata or file changed on disk, or its file went missing
+    TrackUpdated(TrackSummary),
+    ScanFilesFound {
+        path: String,
+        files: usize,
+    },
+    ScanFileImported {
+        file_path: String,
+        track_id: LibraryId<Track>,
+    },
+    ScanFileFailed {
+        file_path: String,
+        reason: String,
+    },
+    PlaylistCreated {
+        playlist_id: LibraryId<Playlist>,
+        name: String,
+    },
+    PlaylistRenamed {
+        playlist_id: LibraryId<Playlist>,
+        name: String,
+    },
+    PlaylistDeleted {
+        playlist_id: LibraryId<Playlist>,
+    },
     TrackAddedToPlaylist {
         track_id: LibraryId<Track>,
         playlist_id: LibraryId<Playlist>,
+        index: usize,
+    },
+    PlaylistTrackMoved {
+        playlist_id: LibraryId<Playlist>,
+        from: usize,
+        to: usize,
+    },
+    TrackRemovedFromPlaylist {
+        playlist_id: LibraryId<Playlist>,
+        index: usize,
     },
 }
 
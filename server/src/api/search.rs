@@ -1,24 +1,413 @@
+use crate::errors::Try;
 use crate::ids::{Album, Artist, Entity, ExternalId, LibraryId, Track};
 use crate::model::{AlbumInfo, ArtistInfo, TrackInfo};
+use crate::services::{Service, ServiceId};
+use anyhow::Context;
 use serde_derive::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Serialize)]
+/// How far apart the durations of two tracks with the same artist and title can be for them to
+/// still count as the same recording.
+const SAME_DURATION_SECS: f32 = 2.0;
+
+#[derive(Serialize, Default)]
 pub struct SearchResults {
     pub tracks: Vec<TrackSearchResult>,
     pub albums: Vec<SearchResult<Album, AlbumInfo>>,
     pub artists: Vec<SearchResult<Artist, ArtistInfo>>,
+    /// How the search went on each service that was searched, by service ID
+    pub services: BTreeMap<String, ServiceSearch>,
+}
+
+/// How searching one service went.
+#[derive(Serialize, Default)]
+pub struct ServiceSearch {
+    /// How many tracks, albums and artists the service found, before they were merged with the
+    /// other services' results
+    pub found: usize,
+    /// Why the service couldn't be searched, if it couldn't
+    pub error: Option<String>,
+}
+
+impl ServiceSearch {
+    fn failed(error: String) -> Self {
+        ServiceSearch {
+            found: 0,
+            error: Some(error),
+        }
+    }
+}
+
+impl SearchResults {
+    /// Adds another set of results after these ones, merging those which are already here into
+    /// them.
+    pub fn merge(&mut self, other: SearchResults) {
+        for track in other.tracks {
+            match self.tracks.iter_mut().find(|t| same_track(t, &track)) {
+                Some(existing) => {
+                    existing.track.merge(track.track);
+                    existing.artist.merge(track.artist);
+                    existing.album.merge(track.album);
+                }
+                None => self.tracks.push(track),
+            }
+        }
+        for album in other.albums {
+            match self
+                .albums
+                .iter_mut()
+                .find(|a| same_album(&a.info, &album.info))
+            {
+                Some(existing) => existing.merge(album),
+                None => self.albums.push(album),
+            }
+        }
+        for artist in other.artists {
+            let name = normalize(&artist.info.name);
+            match self
+                .artists
+                .iter_mut()
+                .find(|a| normalize(&a.info.name) == name)
+            {
+                Some(existing) => existing.merge(artist),
+                None => self.artists.push(artist),
+            }
+        }
+        self.services.extend(other.services);
+    }
+
+    fn len(&self) -> usize {
+        self.tracks.len() + self.albums.len() + self.artists.len()
+    }
 }
 
+/// Something found by a search, which may be in the library, on a service, or both.
 #[derive(Serialize)]
 pub struct SearchResult<E: Entity, T> {
     pub library_id: Option<LibraryId<E>>,
-    pub external_id: ExternalId<E>,
+    /// The IDs of the thing on each service that found it
+    pub external_ids: Vec<ExternalId<E>>,
     pub info: T,
 }
 
+impl<E: Entity, T> SearchResult<E, T> {
+    /// Adds the IDs of another result for the same thing to this one's.
+    fn merge(&mut self, other: SearchResult<E, T>) {
+        self.library_id = self.library_id.or(other.library_id);
+        for external_id in other.external_ids {
+            if !self.external_ids.contains(&external_id) {
+                self.external_ids.push(external_id);
+            }
+        }
+    }
+}
+
 #[derive(Serialize)]
 pub struct TrackSearchResult {
     pub track: SearchResult<Track, TrackInfo>,
     pub artist: SearchResult<Artist, ArtistInfo>,
     pub album: SearchResult<Album, AlbumInfo>,
 }
+
+/// Searches all the services at once, giving up on those which take longer than the timeout.
+///
+/// Each service's results are resolved before they're merged, so that results which are in the
+/// library are linked to it whichever service found them. Results are merged in the order of the
+/// services' IDs, so that they don't change between searches. Services which fail are reported in
+/// the results' `services` rather than failing the whole search.
+pub fn search_services(
+    services: &HashMap<ServiceId, Arc<dyn Service>>,
+    query: &str,
+    timeout: Duration,
+    resolve: impl Fn(SearchResults) -> Try<SearchResults>,
+) -> SearchResults {
+    let (sender, receiver) = mpsc::channel();
+    for (service_id, service) in services {
+        let sender = sender.clone();
+        let service_id = service_id.clone();
+        let service = Arc::clone(service);
+        let query = query.to_string();
+        thread::spawn(move || {
+            // the receiver is gone if we already gave up on this service
+            let _ = sender.send((service_id, service.search(&query)));
+        });
+    }
+    drop(sender);
+
+    let deadline = Instant::now() + timeout;
+    let mut answers = BTreeMap::new();
+    while answers.len() < services.len() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        match receiver.recv_timeout(deadline - now) {
+            Ok((service_id, results)) => {
+                answers.insert(service_id, results);
+            }
+            Err(_) => break,
+        }
+    }
+
+    let mut results = SearchResults::default();
+    for service_id in services.keys() {
+        if !answers.contains_key(service_id) {
+            log::warn!("search on service {} timed out", service_id.0);
+            results.services.insert(
+                service_id.0.clone(),
+                ServiceSearch::failed("timed out".to_string()),
+            );
+        }
+    }
+    for (service_id, answer) in answers {
+        let answer = answer.and_then(|service_results| {
+            resolve(service_results).context("failed to link results to the library")
+        });
+        match answer {
+            Ok(service_results) => {
+                let found = service_results.len();
+                results.merge(service_results);
+                results
+                    .services
+                    .insert(service_id.0, ServiceSearch { found, error: None });
+            }
+            Err(e) => {
+                log::warn!("search on service {} failed: {:#}", service_id.0, e);
+                results
+                    .services
+                    .insert(service_id.0, ServiceSearch::failed(format!("{:#}", e)));
+            }
+        }
+    }
+    results
+}
+
+/// Returns true iff two results are the same recording: they have the same ISRC, or failing that
+/// the same artist and title and about the same duration.
+fn same_track(a: &TrackSearchResult, b: &TrackSearchResult) -> bool {
+    let (a_info, b_info) = (&a.track.info, &b.track.info);
+    if let (Some(a_isrc), Some(b_isrc)) = (&a_info.isrc, &b_info.isrc) {
+        return a_isrc.eq_ignore_ascii_case(b_isrc);
+    }
+    normalize(&a.artist.info.name) == normalize(&b.artist.info.name)
+        && normalize(&a_info.title) == normalize(&b_info.title)
+        && (a_info.duration_secs - b_info.duration_secs).abs() <= SAME_DURATION_SECS
+}
+
+fn same_album(a: &AlbumInfo, b: &AlbumInfo) -> bool {
+    let album_artist = |info: &AlbumInfo| info.album_artist.as_ref().map(|name| normalize(name));
+    normalize(&a.title) == normalize(&b.title) && album_artist(a) == album_artist(b)
+}
+
+/// Makes names from different sources comparable, ignoring case, spacing and punctuation.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::IdString;
+    use crate::model::LoadedTrack;
+    use crate::services::ExternalTrack;
+
+    /// A service which finds one track, or fails, after a delay.
+    struct FakeService {
+        id: &'static str,
+        delay: Duration,
+        track: Option<(&'static str, Option<&'static str>, f32)>,
+    }
+
+    impl Service for FakeService {
+        fn id(&self) -> ServiceId {
+            ServiceId(self.id.to_string())
+        }
+
+        fn search(&self, _query: &str) -> Try<SearchResults> {
+            thread::sleep(self.delay);
+            let (title, isrc, duration_secs) = self.track.ok_or_else(|| anyhow!("offline"))?;
+            Ok(SearchResults {
+                tracks: vec![TrackSearchResult {
+                    track: self.result(
+                        "track",
+                        TrackInfo {
+                            title: title.to_string(),
+                            isrc: isrc.map(str::to_string),
+                            duration_secs,
+                            file_path: None,
+                            track_number: None,
+                            disc_number: None,
+                            genre: None,
+                            composer: None,
+                        },
+                    ),
+                    artist: self.result(
+                        "artist",
+                        ArtistInfo {
+                            name: "Artist".to_string(),
+                            image_url: None,
+                        },
+                    ),
+                    album: self.result(
+                        "album",
+                        AlbumInfo {
+                            title: "Album".to_string(),
+                            cover_image_url: None,
+                            release_date: None,
+                            album_artist: None,
+                            year: None,
+                            musicbrainz_id: None,
+                        },
+                    ),
+                }],
+                ..SearchResults::default()
+            })
+        }
+
+        fn fetch(&self, _track_id: &IdString<Track>) -> Try<LoadedTrack> {
+            Err(anyhow!("fake services can't fetch tracks"))
+        }
+
+        fn track_info(&self, _track_id: &IdString<Track>) -> Try<ExternalTrack> {
+            Err(anyhow!("fake services don't have track info"))
+        }
+    }
+
+    impl FakeService {
+        fn result<E: Entity, T>(&self, id: &str, info: T) -> SearchResult<E, T> {
+            SearchResult {
+                library_id: None,
+                external_ids: vec![ExternalId {
+                    service: self.id(),
+                    id: IdString::new(id.to_string()),
+                }],
+                info,
+            }
+        }
+    }
+
+    fn search(services: Vec<FakeService>) -> SearchResults {
+        search_and_resolve(services, Ok)
+    }
+
+    fn search_and_resolve(
+        services: Vec<FakeService>,
+        resolve: impl Fn(SearchResults) -> Try<SearchResults>,
+    ) -> SearchResults {
+        let services = services
+            .into_iter()
+            .map(|s| (s.id(), Arc::new(s) as Arc<dyn Service>))
+            .collect();
+        search_services(&services, "query", Duration::from_millis(200), resolve)
+    }
+
+    #[test]
+    fn duplicate_tracks_are_merged() {
+        let results = search(vec![
+            FakeService {
+                id: "a",
+                delay: Duration::from_millis(0),
+                track: Some(("Song", None, 180.0)),
+            },
+            FakeService {
+                id: "b",
+                delay: Duration::from_millis(0),
+                track: Some(("song!", None, 181.5)),
+            },
+            FakeService {
+                id: "c",
+                delay: Duration::from_millis(0),
+                track: Some(("Song (Live)", None, 180.0)),
+            },
+        ]);
+        let titles: Vec<&str> = results
+            .tracks
+            .iter()
+            .map(|t| t.track.info.title.as_str())
+            .collect();
+        assert_eq!(titles, vec!["Song", "Song (Live)"]);
+        let services: Vec<String> = results.tracks[0]
+            .track
+            .external_ids
+            .iter()
+            .map(|id| id.service.0.clone())
+            .collect();
+        assert_eq!(services, vec!["a", "b"]);
+        assert!(results.services.values().all(|s| s.error.is_none()));
+        assert_eq!(results.services["a"].found, 1);
+    }
+
+    #[test]
+    fn results_are_linked_to_the_library_whichever_service_found_them() {
+        let fake_service = |id| FakeService {
+            id,
+            delay: Duration::from_millis(0),
+            track: Some(("Song", None, 180.0)),
+        };
+        let results = search_and_resolve(vec![fake_service("a"), fake_service("b")], |mut r| {
+            for t in &mut r.tracks {
+                if t.track.external_ids[0].service.0 == "b" {
+                    t.track.library_id = Some(LibraryId::new(7));
+                }
+            }
+            Ok(r)
+        });
+        assert_eq!(results.tracks.len(), 1);
+        assert_eq!(results.tracks[0].track.library_id.map(|id| id.0), Some(7));
+    }
+
+    #[test]
+    fn tracks_with_different_isrcs_are_not_merged() {
+        let results = search(vec![
+            FakeService {
+                id: "a",
+                delay: Duration::from_millis(0),
+                track: Some(("Song", Some("GBAYE0601498"), 180.0)),
+            },
+            FakeService {
+                id: "b",
+                delay: Duration::from_millis(0),
+                track: Some(("Song", Some("GBAYE0601499"), 180.0)),
+            },
+        ]);
+        assert_eq!(results.tracks.len(), 2);
+    }
+
+    #[test]
+    fn failing_services_are_reported() {
+        let results = search(vec![
+            FakeService {
+                id: "fast",
+                delay: Duration::from_millis(0),
+                track: Some(("Song", None, 180.0)),
+            },
+            FakeService {
+                id: "broken",
+                delay: Duration::from_millis(0),
+                track: None,
+            },
+            FakeService {
+                id: "slow",
+                delay: Duration::from_secs(2),
+                track: Some(("Other Song", None, 180.0)),
+            },
+        ]);
+        assert_eq!(results.tracks.len(), 1);
+        assert!(results.services["fast"].error.is_none());
+        assert_eq!(
+            results.services["broken"].error.as_ref().unwrap(),
+            "offline"
+        );
+        assert_eq!(
+            results.services["slow"].error.as_ref().unwrap(),
+            "timed out"
+        );
+    }
+}
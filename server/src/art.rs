@@ -0,0 +1,141 @@
+use crate::errors::Try;
+use crate::file_formats::Picture;
+use fstrings::{f, format_args_f};
+use image::{ImageFormat, ImageOutputFormat};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tempfile::NamedTempFile;
+
+/// Longest side of thumbnails, in pixels.
+const THUMBNAIL_SIZE: u32 = 300;
+const THUMBNAIL_QUALITY: u8 = 85;
+
+/// Stores cover art in files named after the hash of their contents, so albums with the same art
+/// share a file and a URL.
+pub struct ArtCache {
+    directory: PathBuf,
+}
+
+impl ArtCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Try<Self> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+        Ok(ArtCache { directory })
+    }
+
+    /// Stores a picture unless it is already stored, and returns the path it is served at. The path
+    /// doesn't include the server's address, since that can change and clients know it anyway.
+    pub fn store(&self, picture: &Picture) -> Try<String> {
+        // ID3 pictures can be links to images elsewhere, which we don't follow
+        if !picture.mime_type.starts_with("image/") {
+            return Err(anyhow!("not an image: {}", picture.mime_type));
+        }
+        let hash = format!("{:x}", Sha256::digest(&picture.data));
+        let path = self.directory.join(&hash);
+        if !path.exists() {
+            write_atomically(&path, &picture.data)?;
+        }
+        Ok(f!("/art/{hash}"))
+    }
+
+    /// Returns the MIME type and contents of a stored picture, or `None` if there's no picture with
+    /// this hash.
+    pub fn get(&self, hash: &str) -> Try<Option<(&'static str, Vec<u8>)>> {
+        if !is_hash(hash) {
+            return Ok(None);
+        }
+        Ok(read_if_exists(&self.directory.join(hash))?.map(|data| (mime_type(&data), data)))
+    }
+
+    /// Returns a JPEG thumbnail of a stored picture, which is made the first time it's asked for.
+    pub fn get_thumbnail(&self, hash: &str) -> Try<Option<Vec<u8>>> {
+        if !is_hash(hash) {
+            return Ok(None);
+        }
+        let path = self.directory.join(f!("{hash}-thumbnail"));
+        if let Some(thumbnail) = read_if_exists(&path)? {
+            return Ok(Some(thumbnail));
+        }
+        let (_, data) = match self.get(hash)? {
+            Some(picture) => picture,
+            None => return Ok(None),
+        };
+        let mut thumbnail = Vec::new();
+        image::load_from_memory(&data)?
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .write_to(&mut thumbnail, ImageOutputFormat::JPEG(THUMBNAIL_QUALITY))?;
+        write_atomically(&path, &thumbnail)?;
+        Ok(Some(thumbnail))
+    }
+}
+
+/// Returns true iff this is a hash we could have named a file after, so that requests can't reach
+/// files outside the cache.
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+fn mime_type(data: &[u8]) -> &'static str {
+    match image::guess_format(data) {
+        Ok(ImageFormat::PNG) => "image/png",
+        Ok(ImageFormat::GIF) => "image/gif",
+        Ok(ImageFormat::BMP) => "image/bmp",
+        Ok(ImageFormat::WEBP) => "image/webp",
+        _ => "image/jpeg",
+    }
+}
+
+fn read_if_exists(path: &Path) -> Try<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes a file under a temporary name first, so that a half written file is never served. The
+/// name is unique, so that scans storing the same picture at once don't write to the same file.
+fn write_atomically(path: &Path, data: &[u8]) -> Try<()> {
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut temporary_file = NamedTempFile::new_in(directory)?;
+    temporary_file.write_all(data)?;
+    temporary_file.persist(path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pictures_are_stored_by_hash() {
+        let directory = tempfile::tempdir().unwrap();
+        let cache = ArtCache::new(directory.path()).unwrap();
+        let picture = Picture {
+            mime_type: "image/png".to_string(),
+            data: b"\x89PNG\r\n\x1a\nnot really a png".to_vec(),
+        };
+        let url = cache.store(&picture).unwrap();
+        assert!(url.starts_with("/art/"));
+        let hash = url["/art/".len()..].to_string();
+        assert!(is_hash(&hash));
+        assert_eq!(
+            cache.get(&hash).unwrap(),
+            Some(("image/png", picture.data.clone()))
+        );
+        // storing it again gives the same path
+        assert_eq!(cache.store(&picture).unwrap(), url);
+    }
+
+    #[test]
+    fn only_hashes_are_looked_up() {
+        assert!(is_hash(&"0123456789abcdef".repeat(4)));
+        assert!(!is_hash("../database.sqlite"));
+        assert!(!is_hash(&"0123456789ABCDEF".repeat(4)));
+    }
+}
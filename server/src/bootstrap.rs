@@ -11,17 +11,17 @@ struct Bootstrap {
     tracks: Vec<String>,
 }
 
-pub fn bootstrap_library(library: &Library) -> Try<()> {
-    log::info!("beginning bootstrap");
+pub fn bootstrap_library(library: &Library, bootstrap_path: &str) -> Try<()> {
+    log::info!("beginning bootstrap from {}", bootstrap_path);
     library.in_transaction(|_| {
-        let b: Bootstrap = serde_yaml::from_reader(BufReader::new(File::open("bootstrap.yml")?))?;
+        let b: Bootstrap = serde_yaml::from_reader(BufReader::new(File::open(bootstrap_path)?))?;
         for (playlist, tracks) in b.playlists {
             log::info!("creating playlist {}", playlist);
             let playlist_id = library.create_playlist(playlist)?;
             for playlist_track in tracks {
                 log::info!("adding track {} to playlist", playlist_track);
                 let track_id = library.add_local_track(playlist_track)?;
-                library.add_track_to_playlist(track_id, playlist_id)?;
+                library.add_track_to_playlist(track_id, playlist_id, None)?;
             }
         }
         for track in b.tracks {
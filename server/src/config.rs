@@ -0,0 +1,67 @@
+use crate::errors::Try;
+use crate::playback::AudioOutput;
+use anyhow::Context;
+use fstrings::{f, format_args_f};
+use serde_derive::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub database_path: String,
+    /// Tracks and playlists to add to the library when the database is empty
+    pub bootstrap_path: String,
+    /// Folders containing the music in the library
+    pub library_roots: Vec<String>,
+    /// Folder to keep cover art read from music files in
+    pub art_cache_path: String,
+    pub audio_output: AudioOutput,
+    pub initial_volume: f32,
+    /// Filter for log messages, in the same format as `RUST_LOG`, which takes precedence over it
+    pub log_level: String,
+    /// IDs of the services to register, or `None` to register every one that is available
+    pub services: Option<Vec<String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            database_path: "yamplayer.sqlite".to_string(),
+            bootstrap_path: "bootstrap.yml".to_string(),
+            library_roots: Vec::new(),
+            art_cache_path: "art".to_string(),
+            audio_output: AudioOutput::default(),
+            initial_volume: 0.5,
+            log_level: "info".to_string(),
+            services: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Try<Config> {
+        let file = File::open(path)
+            .with_context(|| f!("failed to open config file {}", path.display()))?;
+        Ok(serde_yaml::from_reader(BufReader::new(file))
+            .with_context(|| f!("invalid config file {}", path.display()))?)
+    }
+
+    pub fn socket_address(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    /// Returns true iff the service with the given ID should be registered.
+    pub fn service_enabled(&self, service_id: &str) -> bool {
+        match &self.services {
+            Some(services) => services.iter().any(|s| s == service_id),
+            None => true,
+        }
+    }
+}
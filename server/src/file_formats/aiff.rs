@@ -0,0 +1,282 @@
+use super::{Metadata, Tags};
+use crate::errors::Try;
+use crate::model::TrackReader;
+use rodio::Source;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// What the COMM chunk says about the audio.
+struct Format {
+    channels: u16,
+    sample_frames: u32,
+    bits_per_sample: u16,
+    sample_rate: f64,
+    /// Whether samples are little endian, which AIFF-C calls `sowt`
+    little_endian: bool,
+}
+
+/// Reads the metadata of an AIFF or AIFF-C file, from its text chunks or an embedded ID3 tag.
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
+    let mut file = BufReader::new(File::open(&file_path)?);
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    if !is_aiff(&header) {
+        return Err(anyhow!("{} is not an AIFF file", file_path));
+    }
+
+    let mut format = None;
+    let (mut name, mut author) = (None, None);
+    let mut id3_tag = None;
+    loop {
+        let mut chunk_header = [0; 8];
+        if file.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+        let length = u32::from_be_bytes(chunk_header[4..8].try_into()?);
+        match &chunk_header[..4] {
+            b"COMM" => format = Some(parse_comm(&read_chunk(&mut file, length)?)?),
+            b"NAME" => name = Some(read_text_chunk(&mut file, length)?),
+            b"AUTH" => author = Some(read_text_chunk(&mut file, length)?),
+            b"ID3 " | b"id3 " => {
+                let tag = read_chunk(&mut file, length)?;
+                match id3::Tag::read_from(&mut Cursor::new(&tag[..])) {
+                    Ok(tag) => id3_tag = Some(tag),
+                    Err(e) => log::warn!("invalid ID3 tag in {}: {}", file_path, e),
+                }
+            }
+            _ => {
+                file.seek(SeekFrom::Current(i64::from(length) + i64::from(length % 2)))?;
+            }
+        }
+    }
+
+    let format = format.ok_or_else(|| anyhow!("no COMM chunk in {}", file_path))?;
+    let duration_secs = (f64::from(format.sample_frames) / format.sample_rate) as f32;
+    let mut tags = id3_tag.as_ref().map(Tags::from_id3).unwrap_or_default();
+    tags.title = tags.title.or(name);
+    if tags.artists.is_empty() {
+        tags.artists.extend(author);
+    }
+    Ok(tags.into_metadata(file_path, duration_secs))
+}
+
+/// Returns true iff the data starts with the header of an AIFF or AIFF-C file.
+pub fn is_aiff(data: &[u8]) -> bool {
+    data.len() >= 12
+        && &data[..4] == b"FORM"
+        && (&data[8..12] == b"AIFF" || &data[8..12] == b"AIFC")
+}
+
+fn parse_comm(comm: &[u8]) -> Try<Format> {
+    if comm.len() < 18 {
+        return Err(anyhow!("truncated COMM chunk"));
+    }
+    // AIFF-C adds a compression type, which we only understand for uncompressed audio
+    let little_endian = match comm.get(18..22) {
+        None | Some(b"NONE") | Some(b"twos") => false,
+        Some(b"sowt") => true,
+        Some(compression) => {
+            return Err(anyhow!(
+                "unsupported AIFF-C compression {}",
+                String::from_utf8_lossy(compression)
+            ))
+        }
+    };
+    Ok(Format {
+        channels: u16::from_be_bytes(comm[0..2].try_into()?),
+        sample_frames: u32::from_be_bytes(comm[2..6].try_into()?),
+        bits_per_sample: u16::from_be_bytes(comm[6..8].try_into()?),
+        sample_rate: parse_extended(comm[8..18].try_into()?),
+        little_endian,
+    })
+}
+
+/// Converts an 80-bit IEEE 754 extended precision number, which is how AIFF stores sample rates.
+fn parse_extended(bytes: [u8; 10]) -> f64 {
+    let sign = if bytes[0] & 0x80 == 0 { 1.0 } else { -1.0 };
+    let exponent = i32::from(u16::from_be_bytes([bytes[0] & 0x7f, bytes[1]]));
+    let mantissa = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+    if exponent == 0 && mantissa == 0 {
+        return 0.0;
+    }
+    // the mantissa has an explicit integer bit, so it's a 64-bit fixed point number in [1, 2)
+    sign * mantissa as f64 * 2_f64.powi(exponent - 16383 - 63)
+}
+
+fn read_chunk(file: &mut impl Read, length: u32) -> Try<Vec<u8>> {
+    // the length comes from the file, so don't trust it with an allocation
+    let mut chunk = Vec::new();
+    file.by_ref()
+        .take(u64::from(length))
+        .read_to_end(&mut chunk)?;
+    if chunk.len() < length as usize {
+        return Err(anyhow!("truncated chunk"));
+    }
+    if length % 2 == 1 {
+        file.read_exact(&mut [0])?;
+    }
+    Ok(chunk)
+}
+
+fn read_text_chunk(file: &mut impl Read, length: u32) -> Try<String> {
+    Ok(String::from_utf8_lossy(&read_chunk(file, length)?)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string())
+}
+
+/// Plays the uncompressed audio in an AIFF file, which rodio can't decode.
+pub struct AiffSource {
+    reader: Box<dyn TrackReader>,
+    format: Format,
+    /// How many bytes of samples we haven't played yet
+    remaining: u64,
+}
+
+impl AiffSource {
+    pub fn new(mut reader: Box<dyn TrackReader>) -> Try<Self> {
+        let (mut format, mut samples) = (None, None);
+        reader.seek(SeekFrom::Start(12))?;
+        loop {
+            let mut chunk_header = [0; 8];
+            if reader.read_exact(&mut chunk_header).is_err() {
+                break;
+            }
+            let length = u32::from_be_bytes(chunk_header[4..8].try_into()?);
+            let body_start = reader.seek(SeekFrom::Current(0))?;
+            match &chunk_header[..4] {
+                b"COMM" => format = Some(parse_comm(&read_chunk(&mut reader, length)?)?),
+                // the samples start after an offset and block size, which are almost always 0
+                b"SSND" if length >= 8 => {
+                    let mut skip = [0; 4];
+                    reader.read_exact(&mut skip)?;
+                    let skip = u64::from(u32::from_be_bytes(skip));
+                    samples = Some((
+                        body_start + 8 + skip,
+                        u64::from(length).saturating_sub(8 + skip),
+                    ));
+                }
+                _ => {}
+            }
+            let body_end = body_start + u64::from(length) + u64::from(length % 2);
+            reader.seek(SeekFrom::Start(body_end))?;
+        }
+        let format = format.ok_or_else(|| anyhow!("no COMM chunk in AIFF file"))?;
+        match format.bits_per_sample {
+            8 | 16 | 24 | 32 => {}
+            bits => return Err(anyhow!("unsupported AIFF sample size {}", bits)),
+        }
+        let (samples_start, samples_length) =
+            samples.ok_or_else(|| anyhow!("no SSND chunk in AIFF file"))?;
+        reader.seek(SeekFrom::Start(samples_start))?;
+        Ok(AiffSource {
+            reader,
+            format,
+            remaining: samples_length,
+        })
+    }
+}
+
+impl Iterator for AiffSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        let bytes_per_sample = usize::from(self.format.bits_per_sample / 8);
+        if bytes_per_sample as u64 > self.remaining {
+            return None;
+        }
+        let mut buffer = [0; 4];
+        let sample = &mut buffer[..bytes_per_sample];
+        // a truncated file just ends early
+        self.reader.read_exact(sample).ok()?;
+        self.remaining -= bytes_per_sample as u64;
+        // keep the most significant 16 bits, which are first unless the file is little endian
+        let (high, low) = match (bytes_per_sample, self.format.little_endian) {
+            (1, _) => (sample[0], 0),
+            (_, true) => (sample[bytes_per_sample - 1], sample[bytes_per_sample - 2]),
+            (_, false) => (sample[0], sample[1]),
+        };
+        Some(i16::from_be_bytes([high, low]))
+    }
+}
+
+impl Source for AiffSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        // the format never changes within the file
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.format.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.format.sample_rate.round() as u32
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aiff_file(samples: &[i16]) -> Vec<u8> {
+        let mut comm = Vec::new();
+        comm.extend_from_slice(&1_u16.to_be_bytes());
+        comm.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+        comm.extend_from_slice(&16_u16.to_be_bytes());
+        // 44100 as an 80-bit extended float
+        comm.extend_from_slice(&[0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]);
+        let mut ssnd = vec![0; 8];
+        for sample in samples {
+            ssnd.extend_from_slice(&sample.to_be_bytes());
+        }
+        let mut chunks = Vec::new();
+        for (id, body) in &[(b"COMM", comm), (b"SSND", ssnd)] {
+            chunks.extend_from_slice(*id);
+            chunks.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            chunks.extend_from_slice(body);
+        }
+        let mut file = b"FORM".to_vec();
+        file.extend_from_slice(&(chunks.len() as u32 + 4).to_be_bytes());
+        file.extend_from_slice(b"AIFF");
+        file.extend_from_slice(&chunks);
+        file
+    }
+
+    #[test]
+    fn huge_chunk_lengths_are_errors() {
+        let mut file = Cursor::new(vec![0; 8]);
+        assert!(read_chunk(&mut file, u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn parses_sample_rates() {
+        assert_eq!(
+            parse_extended([0x40, 0x0e, 0xac, 0x44, 0, 0, 0, 0, 0, 0]),
+            44100.0
+        );
+        assert_eq!(
+            parse_extended([0x40, 0x0e, 0xbb, 0x80, 0, 0, 0, 0, 0, 0]),
+            48000.0
+        );
+    }
+
+    #[test]
+    fn decodes_big_endian_samples() {
+        let data = aiff_file(&[0, 1000, -1000, i16::max_value()]);
+        assert!(is_aiff(&data));
+        let source = AiffSource::new(Box::new(Cursor::new(data))).unwrap();
+        assert_eq!(source.channels(), 1);
+        assert_eq!(source.sample_rate(), 44100);
+        assert_eq!(
+            source.collect::<Vec<_>>(),
+            vec![0, 1000, -1000, i16::max_value()]
+        );
+    }
+}
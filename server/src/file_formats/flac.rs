@@ -1,42 +1,52 @@
+use super::{choose_cover, parse_picture_block, Metadata, Picture, Tags};
 use crate::errors::Try;
-use crate::model::{AlbumInfo, ArtistInfo, TrackInfo};
-use fstrings::{f, format_args_f};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
 
-pub fn read_metadata(file_path: String) -> Try<(TrackInfo, AlbumInfo, ArtistInfo)> {
+/// Type of the metadata blocks which hold pictures.
+const PICTURE: u8 = 6;
+
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
     let flac = claxon::FlacReader::open(&file_path)?;
-    let tag = |name: &str| {
-        flac.get_tag(name)
-            .next()
-            .map(|t| t.to_string())
-            .unwrap_or_else(|| {
-                log::warn!("no {} tag in {}", name, file_path);
-                f!("UNKNOWN {name}")
-            })
-    };
+    let tags = Tags::from_vorbis_comments(|name| flac.get_tag(name).map(str::to_string).collect());
     let duration_secs = (flac
         .streaminfo()
         .samples
         .unwrap_or_else(|| panic!("no stream info in {}", file_path))
         as f32)
         / flac.streaminfo().sample_rate as f32;
-    let track_title = tag("TITLE");
-    let album_title = tag("ALBUM");
-    let artist_name = tag("ARTIST");
-    Ok((
-        TrackInfo {
-            title: track_title,
-            isrc: None,
-            duration_secs,
-            file_path: Some(file_path),
-        },
-        AlbumInfo {
-            title: album_title,
-            cover_image_url: None,
-            release_date: None,
-        },
-        ArtistInfo {
-            name: artist_name,
-            image_url: None,
-        },
-    ))
+    Ok(tags.into_metadata(file_path, duration_secs))
+}
+
+/// Reads the front cover from the picture blocks of a FLAC file, or else the first picture there
+/// is.
+pub fn read_cover_art(file_path: &str) -> Try<Option<Picture>> {
+    // claxon skips over picture blocks, so we read the metadata blocks ourselves
+    let mut file = BufReader::new(File::open(file_path)?);
+    let mut marker = [0; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != b"fLaC" {
+        return Err(anyhow!("{} is not a FLAC file", file_path));
+    }
+    let mut pictures = Vec::new();
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        let is_last = header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        if header[0] & 0x7f == PICTURE {
+            let mut block = vec![0; length as usize];
+            file.read_exact(&mut block)?;
+            match parse_picture_block(&block) {
+                Ok(picture) => pictures.push(picture),
+                Err(e) => log::warn!("invalid picture in {}: {:#}", file_path, e),
+            }
+        } else {
+            file.seek(SeekFrom::Current(i64::from(length)))?;
+        }
+        if is_last {
+            break;
+        }
+    }
+    Ok(choose_cover(pictures))
 }
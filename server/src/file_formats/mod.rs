@@ -1,2 +1,580 @@
+pub mod aiff;
 pub mod flac;
 pub mod mp3;
+pub mod mp4;
+pub mod ogg;
+pub mod wav;
+
+use crate::errors::Try;
+use crate::model::{AlbumInfo, ArtistInfo, ArtistRole, OpenTrack, TrackInfo, TrackReader};
+use anyhow::Context;
+use chrono::NaiveDate;
+use fstrings::{f, format_args_f};
+use rodio::{Decoder, Source};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// What we know about a track from reading its file, including every artist credited on it with
+/// the primary artists first.
+pub type Metadata = (TrackInfo, AlbumInfo, Vec<(ArtistInfo, ArtistRole)>);
+
+/// How much of a track file we look at to tell what format it's in.
+const HEADER_BYTES: u64 = 512;
+
+/// A picture embedded in a file, such as its cover art.
+pub struct Picture {
+    pub mime_type: String,
+    pub data: Vec<u8>,
+}
+
+/// Returns true iff we know how to import the file at this path, going by its extension.
+pub fn is_supported(path: &Path) -> bool {
+    match extension(path).as_str() {
+        "mp3" | "flac" | "ogg" | "oga" | "opus" | "m4a" | "mp4" | "wav" | "aif" | "aiff"
+        | "aifc" => true,
+        _ => false,
+    }
+}
+
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
+    match extension(Path::new(&file_path)).as_str() {
+        "mp3" => mp3::read_metadata(file_path),
+        "flac" => flac::read_metadata(file_path),
+        "ogg" | "oga" | "opus" => ogg::read_metadata(file_path),
+        "m4a" | "mp4" => mp4::read_metadata(file_path),
+        "wav" => wav::read_metadata(file_path),
+        "aif" | "aiff" | "aifc" => aiff::read_metadata(file_path),
+        _ => Err(anyhow!("unsupported file type {}", file_path)),
+    }
+}
+
+/// Returns a function which opens a track file when it's called, rather than straight away.
+pub fn open_lazily(file_path: String) -> OpenTrack {
+    Box::new(move || -> Try<Box<dyn TrackReader>> {
+        let file =
+            File::open(&file_path).with_context(|| f!("failed to open track file {file_path}"))?;
+        Ok(Box::new(BufReader::new(file)))
+    })
+}
+
+/// Creates a decoder for the contents of a track file, going by the data itself rather than the
+/// file name.
+pub fn decode(mut reader: Box<dyn TrackReader>) -> Try<Box<dyn Source<Item = i16> + Send>> {
+    let mut header = Vec::new();
+    reader
+        .by_ref()
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)?;
+    reader.seek(SeekFrom::Start(0))?;
+    if ogg::is_opus(&header) {
+        Ok(Box::new(ogg::OpusSource::new(reader)?))
+    } else if mp4::is_mp4(&header) {
+        Ok(Box::new(mp4::AacSource::new(reader)?))
+    } else if aiff::is_aiff(&header) {
+        Ok(Box::new(aiff::AiffSource::new(reader)?))
+    } else {
+        // rodio can decode everything else we import
+        Ok(Box::new(Decoder::new(reader)?))
+    }
+}
+
+/// Name of the user-defined tag Picard stores MusicBrainz release IDs in, in ID3 and MP4 files.
+const MUSICBRAINZ_ALBUM_ID: &str = "MusicBrainz Album Id";
+
+/// Tags read from a file, whatever format they were stored in.
+#[derive(Default)]
+struct Tags {
+    title: Option<String>,
+    album: Option<String>,
+    /// Artist tags, each of which may name several artists
+    artists: Vec<String>,
+    remixers: Vec<String>,
+    album_artist: Option<String>,
+    track_number: Option<u32>,
+    disc_number: Option<u32>,
+    genre: Option<String>,
+    /// A year, or a full date in `YYYY-MM-DD` format
+    date: Option<String>,
+    /// Composer tags, each of which may name several composers
+    composers: Vec<String>,
+    isrc: Option<String>,
+    musicbrainz_album_id: Option<String>,
+}
+
+impl Tags {
+    /// Reads the standard fields of Vorbis comments, as used by FLAC and Ogg files. Fields may be
+    /// repeated, so `comments` returns every value of a field.
+    fn from_vorbis_comments(comments: impl Fn(&str) -> Vec<String>) -> Self {
+        let comment = |name: &str| comments(name).into_iter().next();
+        Tags {
+            title: comment("TITLE"),
+            album: comment("ALBUM"),
+            artists: comments("ARTIST"),
+            remixers: comments("REMIXER"),
+            album_artist: comment("ALBUMARTIST").or_else(|| comment("ALBUM ARTIST")),
+            track_number: comment("TRACKNUMBER").and_then(|n| parse_number(&n)),
+            disc_number: comment("DISCNUMBER").and_then(|n| parse_number(&n)),
+            genre: comment("GENRE"),
+            date: comment("DATE"),
+            composers: comments("COMPOSER"),
+            isrc: comment("ISRC"),
+            musicbrainz_album_id: comment("MUSICBRAINZ_ALBUMID"),
+        }
+    }
+
+    fn from_id3(tag: &id3::Tag) -> Self {
+        let text = |id: &str| {
+            tag.get(id)
+                .and_then(|frame| frame.content().text())
+                .map(str::to_string)
+        };
+        // several values may be given by repeating a frame, or in ID3v2.4 by separating them with
+        // nulls, which split_artists splits on
+        let texts = |id: &str| -> Vec<String> {
+            tag.frames()
+                .into_iter()
+                .filter(|frame| frame.id() == id)
+                .filter_map(|frame| frame.content().text())
+                .map(str::to_string)
+                .collect()
+        };
+        Tags {
+            title: tag.title().map(str::to_string),
+            album: tag.album().map(str::to_string),
+            artists: texts("TPE1"),
+            remixers: texts("TPE4"),
+            album_artist: tag.album_artist().map(str::to_string),
+            track_number: tag.track(),
+            disc_number: tag.disc(),
+            genre: tag.genre().map(str::to_string),
+            date: tag
+                .date_recorded()
+                .map(|d| d.to_string())
+                .or_else(|| tag.year().map(|y| y.to_string())),
+            composers: texts("TCOM"),
+            isrc: text("TSRC"),
+            musicbrainz_album_id: tag
+                .extended_texts()
+                .find(|t| t.description == MUSICBRAINZ_ALBUM_ID)
+                .map(|t| t.value.clone()),
+        }
+    }
+
+    /// Builds metadata from whichever tags the file had, naming the track after the file if it had
+    /// no title.
+    fn into_metadata(self, file_path: String, duration_secs: f32) -> Metadata {
+        let title = self
+            .title
+            .unwrap_or_else(|| title_from_file_name(&file_path));
+        let tag = |name: &str, value: Option<String>| {
+            value.unwrap_or_else(|| {
+                log::warn!("no {} tag in {}", name, file_path);
+                f!("UNKNOWN {name}")
+            })
+        };
+        let album_title = tag("ALBUM", self.album);
+        let mut credits = Vec::new();
+        for artists in &self.artists {
+            let (primary, featured) = parse_artists(artists);
+            credits.extend(primary.into_iter().map(|name| (name, ArtistRole::Primary)));
+            credits.extend(
+                featured
+                    .into_iter()
+                    .map(|name| (name, ArtistRole::Featured)),
+            );
+        }
+        // featured artists are often only mentioned in the title, as in `Song (feat. Artist)`
+        if let Some((_, end)) = find_featuring(&title) {
+            let featured = split_artists(&title[end..]);
+            credits.extend(
+                featured
+                    .into_iter()
+                    .map(|name| (name, ArtistRole::Featured)),
+            );
+        }
+        for remixers in &self.remixers {
+            let remixers = split_artists(remixers);
+            credits.extend(remixers.into_iter().map(|name| (name, ArtistRole::Remixer)));
+        }
+        let composers: Vec<String> = self
+            .composers
+            .iter()
+            .flat_map(|composers| split_artists(composers))
+            .collect();
+        credits.extend(
+            composers
+                .iter()
+                .map(|name| (name.clone(), ArtistRole::Composer)),
+        );
+        if !credits.iter().any(|(_, role)| *role == ArtistRole::Primary) {
+            credits.insert(0, (tag("ARTIST", None), ArtistRole::Primary));
+        }
+        // sorting is stable, so artists stay in the order the tags named them
+        credits.sort_by_key(|(_, role)| *role != ArtistRole::Primary);
+        let mut seen = HashSet::new();
+        credits.retain(|credit| seen.insert(credit.clone()));
+        // albums are told apart by who they're by, so every album needs an artist
+        let album_artist = self.album_artist.unwrap_or_else(|| credits[0].0.clone());
+        let year = self
+            .date
+            .as_ref()
+            .and_then(|d| d.get(..4))
+            .and_then(|y| y.parse().ok());
+        let release_date = self
+            .date
+            .as_ref()
+            // some formats store a full timestamp
+            .and_then(|d| d.get(..10))
+            .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok());
+        (
+            TrackInfo {
+                title,
+                isrc: self.isrc,
+                duration_secs,
+                file_path: Some(file_path),
+                track_number: self.track_number,
+                disc_number: self.disc_number,
+                genre: self.genre,
+                composer: if composers.is_empty() {
+                    None
+                } else {
+                    Some(composers.join("; "))
+                },
+            },
+            AlbumInfo {
+                title: album_title,
+                cover_image_url: None,
+                release_date,
+                album_artist: Some(album_artist),
+                year,
+                musicbrainz_id: self.musicbrainz_album_id,
+            },
+            credits
+                .into_iter()
+                .map(|(name, role)| {
+                    (
+                        ArtistInfo {
+                            name,
+                            image_url: None,
+                        },
+                        role,
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Ways of introducing featured artists, in lower case.
+const FEATURING: &[&str] = &[" featuring ", " feat. ", " feat ", " ft. ", " ft "];
+
+/// Splits an artist tag such as `A feat. B` into the primary and the featured artists.
+/// Separators like `&` and `,` are left alone, since they're part of names such as
+/// `Earth, Wind & Fire` as often as they separate names.
+fn parse_artists(artists: &str) -> (Vec<String>, Vec<String>) {
+    match find_featuring(artists) {
+        Some((start, end)) => (
+            split_artists(&artists[..start]),
+            split_artists(&artists[end..]),
+        ),
+        None => (split_artists(artists), vec![]),
+    }
+}
+
+/// Finds where the first mention of featured artists starts and ends, including any bracket before
+/// it.
+fn find_featuring(text: &str) -> Option<(usize, usize)> {
+    // brackets are ASCII, so replacing them with spaces doesn't move anything
+    let lower = text
+        .to_ascii_lowercase()
+        .replace(|c: char| c == '(' || c == '[', " ");
+    FEATURING
+        .iter()
+        .filter_map(|featuring| lower.find(featuring).map(|i| (i, i + featuring.len())))
+        .min()
+}
+
+/// Splits the null separated names of several artists, as in ID3v2.4 tags, dropping any closing
+/// bracket left over from the title.
+fn split_artists(artists: &str) -> Vec<String> {
+    artists
+        .split('\0')
+        .map(|name| {
+            name.trim()
+                .trim_end_matches(|c: char| c == ')' || c == ']')
+                .trim()
+        })
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Picture type of the front cover, from the FLAC picture block spec, which ID3 shares.
+const FRONT_COVER: u32 = 3;
+
+/// Names of image files which hold the cover of the album in their folder, in order of preference.
+const COVER_FILE_NAMES: &[&str] = &[
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png",
+    "folder.jpg",
+    "folder.jpeg",
+    "folder.png",
+    "front.jpg",
+    "front.png",
+];
+
+/// Reads the cover art embedded in a track file, or else from an image file in the same folder.
+pub fn read_cover_art(file_path: &str) -> Try<Option<Picture>> {
+    let embedded = match extension(Path::new(file_path)).as_str() {
+        "mp3" => mp3::read_cover_art(file_path)?,
+        "flac" => flac::read_cover_art(file_path)?,
+        "ogg" | "oga" | "opus" => ogg::read_cover_art(file_path)?,
+        "m4a" | "mp4" => mp4::read_cover_art(file_path)?,
+        _ => None,
+    };
+    match embedded {
+        Some(picture) => Ok(Some(picture)),
+        None => read_cover_file(file_path),
+    }
+}
+
+/// Reads the image file holding the cover of the album a track file is in, such as `cover.jpg`.
+fn read_cover_file(file_path: &str) -> Try<Option<Picture>> {
+    let folder = match Path::new(file_path).parent() {
+        Some(folder) => folder,
+        None => return Ok(None),
+    };
+    let mut covers = Vec::new();
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+        let file_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        if let Some(preference) = COVER_FILE_NAMES.iter().position(|n| *n == file_name) {
+            covers.push((preference, path));
+        }
+    }
+    let path = match covers.into_iter().min() {
+        Some((_, path)) => path,
+        None => return Ok(None),
+    };
+    let mime_type = match extension(&path).as_str() {
+        "png" => "image/png",
+        _ => "image/jpeg",
+    };
+    Ok(Some(Picture {
+        mime_type: mime_type.to_string(),
+        data: fs::read(&path)?,
+    }))
+}
+
+/// Picks the front cover out of the pictures in a file, along with their picture types, or else
+/// the first picture.
+fn choose_cover(mut pictures: Vec<(u32, Picture)>) -> Option<Picture> {
+    match pictures.iter().position(|(t, _)| *t == FRONT_COVER) {
+        Some(index) => Some(pictures.swap_remove(index).1),
+        None => pictures.into_iter().next().map(|(_, p)| p),
+    }
+}
+
+/// Parses a FLAC picture block, as found in FLAC files and base64 encoded in the
+/// METADATA_BLOCK_PICTURE comments of Ogg files, returning the picture type along with the picture.
+fn parse_picture_block(block: &[u8]) -> Try<(u32, Picture)> {
+    let mut offset = 0;
+    let picture_type = u32_be(take(block, &mut offset, 4)?)?;
+    let mime_type_length = u32_be(take(block, &mut offset, 4)?)? as usize;
+    let mime_type = String::from_utf8(take(block, &mut offset, mime_type_length)?.to_vec())?;
+    let description_length = u32_be(take(block, &mut offset, 4)?)? as usize;
+    take(block, &mut offset, description_length)?;
+    // width, height, colour depth and number of colours, which we don't need
+    take(block, &mut offset, 16)?;
+    let data_length = u32_be(take(block, &mut offset, 4)?)? as usize;
+    let data = take(block, &mut offset, data_length)?.to_vec();
+    Ok((picture_type, Picture { mime_type, data }))
+}
+
+fn take<'a>(data: &'a [u8], offset: &mut usize, length: usize) -> Try<&'a [u8]> {
+    let bytes = data
+        .get(*offset..*offset + length)
+        .ok_or_else(|| anyhow!("truncated picture block"))?;
+    *offset += length;
+    Ok(bytes)
+}
+
+fn u32_be(bytes: &[u8]) -> Try<u32> {
+    Ok(u32::from_be_bytes(bytes.try_into()?))
+}
+
+/// Parses a track or disc number, which may be followed by the total, as in `3/12`.
+fn parse_number(number: &str) -> Option<u32> {
+    number.split('/').next()?.trim().parse().ok()
+}
+
+/// Guesses a track title from a file name such as `03 - Title.wav`.
+fn title_from_file_name(file_path: &str) -> String {
+    let stem = Path::new(file_path)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let without_number = stem.trim_start_matches(|c: char| c.is_ascii_digit());
+    let is_separator = |c: char| c == ' ' || c == '-' || c == '.' || c == '_';
+    // only strip a track number if something separates it from the rest of the name
+    if without_number.len() < stem.len() && without_number.starts_with(is_separator) {
+        let title = without_number.trim_start_matches(is_separator);
+        if !title.is_empty() {
+            return title.to_string();
+        }
+    }
+    stem
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles_from_file_names_skip_track_numbers() {
+        assert_eq!(title_from_file_name("/music/03 - Song.wav"), "Song");
+        assert_eq!(title_from_file_name("/music/12. Song.aiff"), "Song");
+        assert_eq!(title_from_file_name("/music/1979.wav"), "1979");
+        assert_eq!(title_from_file_name("/music/01 Song.wav"), "Song");
+        assert_eq!(title_from_file_name("/music/Song.wav"), "Song");
+    }
+
+    #[test]
+    fn parses_picture_blocks() {
+        let mut block = Vec::new();
+        block.extend_from_slice(&FRONT_COVER.to_be_bytes());
+        block.extend_from_slice(&10_u32.to_be_bytes());
+        block.extend_from_slice(b"image/jpeg");
+        block.extend_from_slice(&5_u32.to_be_bytes());
+        block.extend_from_slice(b"cover");
+        block.extend_from_slice(&[0; 16]);
+        block.extend_from_slice(&3_u32.to_be_bytes());
+        block.extend_from_slice(&[1, 2, 3]);
+        let (picture_type, picture) = parse_picture_block(&block).unwrap();
+        assert_eq!(picture_type, FRONT_COVER);
+        assert_eq!(picture.mime_type, "image/jpeg");
+        assert_eq!(picture.data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn track_numbers_may_include_the_total() {
+        assert_eq!(parse_number("3"), Some(3));
+        assert_eq!(parse_number("03/12"), Some(3));
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn dates_give_a_year_and_maybe_a_release_date() {
+        let metadata = |date: &str| {
+            Tags {
+                date: Some(date.to_string()),
+                ..Tags::default()
+            }
+            .into_metadata("/music/Song.flac".to_string(), 1.0)
+        };
+        let (_, album, _) = metadata("1997");
+        assert_eq!(album.year, Some(1997));
+        assert_eq!(album.release_date, None);
+        let (_, album, _) = metadata("1997-05-21");
+        assert_eq!(album.year, Some(1997));
+        assert_eq!(album.release_date, NaiveDate::from_ymd_opt(1997, 5, 21));
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn artist_tags_can_name_several_artists() {
+        assert_eq!(parse_artists("AC/DC"), (names(&["AC/DC"]), vec![]));
+        assert_eq!(parse_artists("A\0B"), (names(&["A", "B"]), vec![]));
+        assert_eq!(
+            parse_artists("Earth, Wind & Fire feat. C\0D"),
+            (names(&["Earth, Wind & Fire"]), names(&["C", "D"]))
+        );
+        assert_eq!(parse_artists("A Ft. B"), (names(&["A"]), names(&["B"])));
+        assert_eq!(
+            parse_artists("A (featuring B)"),
+            (names(&["A"]), names(&["B"]))
+        );
+    }
+
+    #[test]
+    fn credits_come_from_every_artist_tag() {
+        let (_, _, artists) = Tags {
+            title: Some("Song (feat. C)".to_string()),
+            artists: names(&["A", "B feat. C"]),
+            remixers: names(&["D"]),
+            composers: names(&["E\0A"]),
+            ..Tags::default()
+        }
+        .into_metadata("/music/Song.flac".to_string(), 1.0);
+        let credits: Vec<_> = artists
+            .iter()
+            .map(|(artist, role)| (artist.name.as_str(), *role))
+            .collect();
+        assert_eq!(
+            credits,
+            vec![
+                ("A", ArtistRole::Primary),
+                ("B", ArtistRole::Primary),
+                ("C", ArtistRole::Featured),
+                ("D", ArtistRole::Remixer),
+                ("E", ArtistRole::Composer),
+                ("A", ArtistRole::Composer),
+            ]
+        );
+    }
+
+    #[test]
+    fn albums_are_by_the_album_artist_or_else_the_track_artist() {
+        let album_artist = |album_artist: Option<&str>| {
+            let (_, album, _) = Tags {
+                artists: names(&["A feat. B"]),
+                album_artist: album_artist.map(str::to_string),
+                ..Tags::default()
+            }
+            .into_metadata("/music/Song.flac".to_string(), 1.0);
+            album.album_artist
+        };
+        assert_eq!(album_artist(None), Some("A".to_string()));
+        // names with separators in them aren't broken up
+        let (_, album, artists) = Tags {
+            artists: names(&["Simon & Garfunkel"]),
+            ..Tags::default()
+        }
+        .into_metadata("/music/Song.flac".to_string(), 1.0);
+        assert_eq!(album.album_artist, Some("Simon & Garfunkel".to_string()));
+        assert_eq!(artists.len(), 1);
+        assert_eq!(
+            album_artist(Some("Various Artists")),
+            Some("Various Artists".to_string())
+        );
+    }
+
+    #[test]
+    fn tracks_without_artists_are_by_an_unknown_artist() {
+        let (_, _, artists) = Tags {
+            composers: names(&["E"]),
+            ..Tags::default()
+        }
+        .into_metadata("/music/Song.flac".to_string(), 1.0);
+        assert_eq!(artists[0].0.name, "UNKNOWN ARTIST");
+        assert_eq!(artists[0].1, ArtistRole::Primary);
+        assert_eq!(artists.len(), 2);
+    }
+}
@@ -1,19 +1,13 @@
+use super::{choose_cover, Metadata, Picture, Tags, FRONT_COVER};
 use crate::errors::Try;
-use crate::model::{AlbumInfo, ArtistInfo, TrackInfo};
-use fstrings::{f, format_args_f};
+use id3::frame::PictureType;
 use id3::Tag;
 use std::fs::File;
 use std::io::BufReader;
 
-pub fn read_metadata(file_path: String) -> Try<(TrackInfo, AlbumInfo, ArtistInfo)> {
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
     // TODO: move mp3 handling code to separate module
     let mp3_tags = Tag::read_from_path(&file_path)?;
-    let tag = |name: &str, value: Option<&str>| {
-        value.map(|t| t.to_string()).unwrap_or_else(|| {
-            log::warn!("no {} tag in {}", name, file_path);
-            f!("UNKNOWN {name}")
-        })
-    };
     let mut mp3 = minimp3::Decoder::new(BufReader::new(File::open(&file_path)?));
     let mut duration_secs = 0_f32;
     loop {
@@ -35,24 +29,27 @@ pub fn read_metadata(file_path: String) -> Try<(TrackInfo, AlbumInfo, ArtistInfo
             (frame.data.len() / frame.channels) as f32 / frame.sample_rate as f32;
         duration_secs += seconds_of_audio;
     }
-    let track_title = tag("TITLE", mp3_tags.title());
-    let album_title = tag("ALBUM", mp3_tags.album());
-    let artist_name = tag("ARTIST", mp3_tags.artist());
-    Ok((
-        TrackInfo {
-            title: track_title,
-            isrc: None,
-            duration_secs,
-            file_path: Some(file_path),
-        },
-        AlbumInfo {
-            title: album_title,
-            cover_image_url: None,
-            release_date: None,
-        },
-        ArtistInfo {
-            name: artist_name,
-            image_url: None,
-        },
-    ))
+    Ok(Tags::from_id3(&mp3_tags).into_metadata(file_path, duration_secs))
+}
+
+/// Reads the front cover from the APIC frames of an MP3 file's ID3 tag, or else the first picture
+/// there is.
+pub fn read_cover_art(file_path: &str) -> Try<Option<Picture>> {
+    let tag = Tag::read_from_path(file_path)?;
+    let pictures = tag
+        .pictures()
+        .map(|picture| {
+            // we only care whether it's the front cover, so the other types can all be 0, "other"
+            let picture_type = match picture.picture_type {
+                PictureType::CoverFront => FRONT_COVER,
+                _ => 0,
+            };
+            let picture = Picture {
+                mime_type: picture.mime_type.clone(),
+                data: picture.data.clone(),
+            };
+            (picture_type, picture)
+        })
+        .collect();
+    Ok(choose_cover(pictures))
 }
@@ -0,0 +1,657 @@
+use super::{Metadata, Picture, Tags, MUSICBRAINZ_ALBUM_ID};
+use crate::errors::Try;
+use crate::model::TrackReader;
+use fdk_aac::dec::{Decoder, Transport};
+use rodio::Source;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::Duration;
+use std::vec;
+
+const TITLE: [u8; 4] = *b"\xa9nam";
+const ALBUM: [u8; 4] = *b"\xa9alb";
+const ARTIST: [u8; 4] = *b"\xa9ART";
+const ALBUM_ARTIST: [u8; 4] = *b"aART";
+const GENRE: [u8; 4] = *b"\xa9gen";
+const DATE: [u8; 4] = *b"\xa9day";
+const COMPOSER: [u8; 4] = *b"\xa9wrt";
+const TRACK_NUMBER: [u8; 4] = *b"trkn";
+const DISC_NUMBER: [u8; 4] = *b"disk";
+const COVER_ART: [u8; 4] = *b"covr";
+
+/// Most samples an AAC frame can decode to: 2048 per channel with SBR, for up to 8 channels.
+const AAC_MAX_FRAME_SAMPLES: usize = 2048 * 8;
+
+/// A box in an MP4 file, or an atom as they're called in iTunes metadata.
+struct Atom<'a> {
+    kind: [u8; 4],
+    body: &'a [u8],
+}
+
+/// Reads the metadata of an MP4 audio file, such as an iTunes `.m4a`.
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
+    let moov = read_moov(&mut BufReader::new(File::open(&file_path)?))?;
+    let tags = read_tags(&moov)?;
+    let text = |kind: &[u8; 4]| {
+        tags.get(kind)
+            .map(|(_, value)| String::from_utf8_lossy(value).into_owned())
+    };
+    let number = |kind: &[u8; 4]| {
+        tags.get(kind)
+            .and_then(|(_, value)| number_and_total(value))
+            .map(|(number, _)| u32::from(number))
+            // 0 means there's only a total
+            .filter(|n| *n > 0)
+    };
+    let duration_secs = read_duration(&moov)?;
+    let tags = Tags {
+        title: text(&TITLE),
+        album: text(&ALBUM),
+        artists: text(&ARTIST).into_iter().collect(),
+        remixers: vec![],
+        album_artist: text(&ALBUM_ARTIST),
+        track_number: number(&TRACK_NUMBER),
+        disc_number: number(&DISC_NUMBER),
+        genre: text(&GENRE),
+        date: text(&DATE),
+        composers: text(&COMPOSER).into_iter().collect(),
+        isrc: None,
+        musicbrainz_album_id: read_freeform_tag(&moov, MUSICBRAINZ_ALBUM_ID)?,
+    };
+    Ok(tags.into_metadata(file_path, duration_secs))
+}
+
+/// Reads the first cover picture embedded in an MP4 file.
+pub fn read_cover_art(file_path: &str) -> Try<Option<Picture>> {
+    let moov = read_moov(&mut BufReader::new(File::open(file_path)?))?;
+    Ok(read_tags(&moov)?
+        .remove(&COVER_ART)
+        .and_then(|(type_indicator, data)| {
+            let mime_type = match type_indicator {
+                13 => "image/jpeg",
+                14 => "image/png",
+                27 => "image/bmp",
+                _ => return None,
+            };
+            Some(Picture {
+                mime_type: mime_type.to_string(),
+                data,
+            })
+        }))
+}
+
+/// Parses a `trkn` or `disk` tag into the number and the total, where the total may be 0 if unknown.
+fn number_and_total(data: &[u8]) -> Option<(u16, u16)> {
+    Some((
+        u16::from_be_bytes(data.get(2..4)?.try_into().ok()?),
+        u16::from_be_bytes(data.get(4..6)?.try_into().ok()?),
+    ))
+}
+
+/// Returns true iff the data looks like an MP4 file, which rodio can't decode.
+pub fn is_mp4(data: &[u8]) -> bool {
+    data.get(4..8) == Some(&b"ftyp"[..])
+}
+
+/// Reads the body of the top level `moov` box, which holds all the metadata, skipping over the
+/// media data which may come before it.
+fn read_moov(reader: &mut (impl Read + Seek)) -> Try<Vec<u8>> {
+    loop {
+        let mut header = [0; 8];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| anyhow!("no moov box found"))?;
+        let kind = &header[4..8];
+        let (header_length, size) = match u32::from_be_bytes(header[..4].try_into()?) {
+            // a 64-bit size follows the type
+            1 => {
+                let mut size = [0; 8];
+                reader.read_exact(&mut size)?;
+                (16, u64::from_be_bytes(size))
+            }
+            // the box extends to the end of the file
+            0 => {
+                let position = reader.seek(SeekFrom::Current(0))?;
+                (8, reader.seek(SeekFrom::End(0))? - position + 8)
+            }
+            size => (8, u64::from(size)),
+        };
+        let body_length = size
+            .checked_sub(header_length)
+            .ok_or_else(|| anyhow!("invalid box size {}", size))?;
+        if kind == b"moov" {
+            // the size comes from the file, so don't trust it with an allocation
+            let mut moov = Vec::new();
+            reader.by_ref().take(body_length).read_to_end(&mut moov)?;
+            if (moov.len() as u64) < body_length {
+                return Err(anyhow!("truncated moov box"));
+            }
+            return Ok(moov);
+        }
+        let body_length: i64 = body_length
+            .try_into()
+            .map_err(|_| anyhow!("invalid box size {}", size))?;
+        reader.seek(SeekFrom::Current(body_length))?;
+    }
+}
+
+/// Parses the boxes that make up some data, such as the body of another box.
+fn atoms(data: &[u8]) -> Try<Vec<Atom>> {
+    let mut atoms = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let header = data
+            .get(offset..offset + 8)
+            .ok_or_else(|| anyhow!("truncated box header"))?;
+        let kind = header[4..8].try_into()?;
+        let (header_length, size) = match u32_be(header, 0)? {
+            1 => (16, u64_be(data, offset + 8)? as usize),
+            0 => (8, data.len() - offset),
+            size => (8, size as usize),
+        };
+        let end = offset
+            .checked_add(size)
+            .ok_or_else(|| anyhow!("invalid box size {}", size))?;
+        let body = data
+            .get(offset + header_length..end)
+            .ok_or_else(|| anyhow!("truncated {} box", String::from_utf8_lossy(&kind)))?;
+        atoms.push(Atom { kind, body });
+        offset += size;
+    }
+    Ok(atoms)
+}
+
+/// Finds the first child box of the given type.
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Try<Option<Atom<'a>>> {
+    Ok(atoms(data)?.into_iter().find(|a| &a.kind == kind))
+}
+
+/// Finds a box by following a path of box types down from the given data.
+fn descend<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Try<Option<&'a [u8]>> {
+    let mut data = data;
+    for kind in path {
+        data = match child(data, kind)? {
+            // meta is a full box, so it has a version and flags before its children
+            Some(atom) if &atom.kind == b"meta" => atom.body.get(4..).unwrap_or_default(),
+            Some(atom) => atom.body,
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(data))
+}
+
+/// Reads the iTunes metadata, returning the type indicator and value of each tag.
+fn read_tags(moov: &[u8]) -> Try<HashMap<[u8; 4], (u32, Vec<u8>)>> {
+    let mut tags = HashMap::new();
+    let ilst = match descend(moov, &[b"udta", b"meta", b"ilst"])? {
+        Some(ilst) => ilst,
+        None => return Ok(tags),
+    };
+    for tag in atoms(ilst)? {
+        if let Some(data) = child(tag.body, b"data")? {
+            // the type indicator is followed by a locale, which we don't care about
+            let type_indicator = u32_be(data.body, 0)?;
+            let value = data.body.get(8..).unwrap_or_default().to_vec();
+            // keep the first of any repeated tags, such as extra covers
+            tags.entry(tag.kind).or_insert((type_indicator, value));
+        }
+    }
+    Ok(tags)
+}
+
+/// Reads a freeform `----` tag, which is how iTunes stores tags that don't have a box type of their
+/// own, such as the ones MusicBrainz Picard writes.
+fn read_freeform_tag(moov: &[u8], name: &str) -> Try<Option<String>> {
+    let ilst = match descend(moov, &[b"udta", b"meta", b"ilst"])? {
+        Some(ilst) => ilst,
+        None => return Ok(None),
+    };
+    for tag in atoms(ilst)?.into_iter().filter(|a| &a.kind == b"----") {
+        // the name is a full box, so it has a version and flags before the name itself
+        let tag_name = child(tag.body, b"name")?.and_then(|a| a.body.get(4..));
+        if tag_name != Some(name.as_bytes()) {
+            continue;
+        }
+        if let Some(data) = child(tag.body, b"data")? {
+            let value = data.body.get(8..).unwrap_or_default();
+            return Ok(Some(String::from_utf8_lossy(value).into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the duration of the audio track, or of the whole movie if there isn't an audio track.
+fn read_duration(moov: &[u8]) -> Try<f32> {
+    if let Some(mdia) = find_audio_media(moov)? {
+        if let Some(mdhd) = child(mdia, b"mdhd")? {
+            return parse_duration(mdhd.body);
+        }
+    }
+    let mvhd = child(moov, b"mvhd")?.ok_or_else(|| anyhow!("no mvhd box"))?;
+    parse_duration(mvhd.body)
+}
+
+/// Parses the timescale and duration out of a `mvhd` or `mdhd` box.
+fn parse_duration(body: &[u8]) -> Try<f32> {
+    let (timescale, duration) = match body.first() {
+        Some(1) => (u32_be(body, 20)?, u64_be(body, 24)?),
+        _ => (u32_be(body, 12)?, u64::from(u32_be(body, 16)?)),
+    };
+    if timescale == 0 {
+        return Err(anyhow!("invalid timescale 0"));
+    }
+    Ok(duration as f32 / timescale as f32)
+}
+
+/// Finds the `mdia` box of the first track whose handler says it contains sound.
+fn find_audio_media(moov: &[u8]) -> Try<Option<&[u8]>> {
+    for trak in atoms(moov)?.into_iter().filter(|a| &a.kind == b"trak") {
+        if let Some(mdia) = child(trak.body, b"mdia")? {
+            if let Some(hdlr) = child(mdia.body, b"hdlr")? {
+                if hdlr.body.get(8..12) == Some(&b"soun"[..]) {
+                    return Ok(Some(mdia.body));
+                }
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// What we need from an MP4 file to decode its audio.
+struct AudioTrack {
+    /// AAC AudioSpecificConfig, from the `esds` box
+    decoder_config: Vec<u8>,
+    /// Where each AAC frame is in the file
+    samples: Vec<Range<u64>>,
+}
+
+/// Finds the audio track in the body of the `moov` box, checking the frames it lists against the
+/// length of the file.
+fn read_audio_track(moov: &[u8], file_length: u64) -> Try<AudioTrack> {
+    let stbl = match find_audio_media(moov)? {
+        Some(mdia) => descend(mdia, &[b"minf", b"stbl"])?,
+        None => None,
+    }
+    .ok_or_else(|| anyhow!("no audio track"))?;
+    let stsd = child(stbl, b"stsd")?.ok_or_else(|| anyhow!("no stsd box"))?;
+    let entry = atoms(stsd.body.get(8..).unwrap_or_default())?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no sample description"))?;
+    if &entry.kind != b"mp4a" {
+        return Err(anyhow!(
+            "unsupported audio codec {}",
+            String::from_utf8_lossy(&entry.kind)
+        ));
+    }
+    // QuickTime sound descriptions get longer with each version
+    let children_start = match u16_be(entry.body, 8)? {
+        0 => 28,
+        1 => 44,
+        2 => 64,
+        version => return Err(anyhow!("unknown sound description version {}", version)),
+    };
+    let esds = child(
+        entry.body.get(children_start..).unwrap_or_default(),
+        b"esds",
+    )?
+    .ok_or_else(|| anyhow!("no esds box"))?;
+    let decoder_config = parse_esds(esds.body)?;
+
+    let stsz = child(stbl, b"stsz")?.ok_or_else(|| anyhow!("no stsz box"))?;
+    let (uniform_size, sample_count) = (u32_be(stsz.body, 4)?, u32_be(stsz.body, 8)? as usize);
+    // the count comes from the file, so check it could be true before allocating for it
+    let needed = match uniform_size {
+        0 => sample_count.checked_mul(4).and_then(|n| n.checked_add(12)),
+        size => sample_count.checked_mul(size as usize),
+    };
+    let available = match uniform_size {
+        0 => stsz.body.len() as u64,
+        _ => file_length,
+    };
+    if needed.map_or(true, |needed| needed as u64 > available) {
+        return Err(anyhow!("invalid sample count {}", sample_count));
+    }
+    let sample_sizes = (0..sample_count)
+        .map(|i| -> Try<u64> {
+            match uniform_size {
+                0 => Ok(u64::from(u32_be(stsz.body, 12 + 4 * i)?)),
+                size => Ok(u64::from(size)),
+            }
+        })
+        .collect::<Try<Vec<u64>>>()?;
+
+    let chunk_offsets = if let Some(stco) = child(stbl, b"stco")? {
+        (0..u32_be(stco.body, 4)? as usize)
+            .map(|i| -> Try<u64> { Ok(u64::from(u32_be(stco.body, 8 + 4 * i)?)) })
+            .collect::<Try<Vec<u64>>>()?
+    } else if let Some(co64) = child(stbl, b"co64")? {
+        (0..u32_be(co64.body, 4)? as usize)
+            .map(|i| -> Try<u64> { u64_be(co64.body, 8 + 8 * i) })
+            .collect::<Try<Vec<u64>>>()?
+    } else {
+        return Err(anyhow!("no chunk offsets"));
+    };
+
+    // runs of chunks with the same number of samples each, by the number of the first chunk
+    let stsc = child(stbl, b"stsc")?.ok_or_else(|| anyhow!("no stsc box"))?;
+    let runs = (0..u32_be(stsc.body, 4)? as usize)
+        .map(|i| -> Try<(usize, usize)> {
+            Ok((
+                u32_be(stsc.body, 8 + 12 * i)? as usize,
+                u32_be(stsc.body, 12 + 12 * i)? as usize,
+            ))
+        })
+        .collect::<Try<Vec<(usize, usize)>>>()?;
+
+    let mut samples = Vec::with_capacity(sample_count);
+    let mut sizes = sample_sizes.into_iter();
+    for (chunk_index, chunk_offset) in chunk_offsets.into_iter().enumerate() {
+        let samples_in_chunk = runs
+            .iter()
+            .take_while(|(first_chunk, _)| *first_chunk <= chunk_index + 1)
+            .last()
+            .map(|(_, samples)| *samples)
+            .unwrap_or_default();
+        let mut offset = chunk_offset;
+        for size in sizes.by_ref().take(samples_in_chunk) {
+            let end = offset
+                .checked_add(size)
+                .ok_or_else(|| anyhow!("invalid chunk offset {}", chunk_offset))?;
+            samples.push(offset..end);
+            offset = end;
+        }
+    }
+    Ok(AudioTrack {
+        decoder_config,
+        samples,
+    })
+}
+
+/// Digs the decoder specific info out of the elementary stream descriptor in an `esds` box.
+fn parse_esds(body: &[u8]) -> Try<Vec<u8>> {
+    // skip the version and flags
+    let mut offset = 4;
+    let (tag, _) = read_descriptor_header(body, &mut offset)?;
+    if tag != 0x03 {
+        return Err(anyhow!("expected ES descriptor, found tag {}", tag));
+    }
+    let flags = *body
+        .get(offset + 2)
+        .ok_or_else(|| anyhow!("truncated ES descriptor"))?;
+    offset += 3;
+    if flags & 0x80 != 0 {
+        // stream dependence
+        offset += 2;
+    }
+    if flags & 0x40 != 0 {
+        // URL
+        offset += 1 + *body.get(offset).unwrap_or(&0) as usize;
+    }
+    if flags & 0x20 != 0 {
+        // OCR stream
+        offset += 2;
+    }
+    let (tag, _) = read_descriptor_header(body, &mut offset)?;
+    if tag != 0x04 {
+        return Err(anyhow!(
+            "expected decoder config descriptor, found tag {}",
+            tag
+        ));
+    }
+    // object type, stream type, buffer size and bitrates
+    offset += 13;
+    let (tag, length) = read_descriptor_header(body, &mut offset)?;
+    if tag != 0x05 {
+        return Err(anyhow!("expected decoder specific info, found tag {}", tag));
+    }
+    Ok(body
+        .get(offset..offset + length)
+        .ok_or_else(|| anyhow!("truncated decoder specific info"))?
+        .to_vec())
+}
+
+/// Reads a descriptor's tag and its length, which is encoded in 7-bit groups.
+fn read_descriptor_header(body: &[u8], offset: &mut usize) -> Try<(u8, usize)> {
+    let mut next_byte = || -> Try<u8> {
+        let byte = *body
+            .get(*offset)
+            .ok_or_else(|| anyhow!("truncated descriptor"))?;
+        *offset += 1;
+        Ok(byte)
+    };
+    let tag = next_byte()?;
+    let mut length = 0;
+    for _ in 0..4 {
+        let byte = next_byte()?;
+        length = (length << 7) | usize::from(byte & 0x7f);
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    Ok((tag, length))
+}
+
+fn u16_be(data: &[u8], offset: usize) -> Try<u16> {
+    Ok(u16::from_be_bytes(
+        data.get(offset..offset + 2)
+            .ok_or_else(|| anyhow!("truncated box"))?
+            .try_into()?,
+    ))
+}
+
+fn u32_be(data: &[u8], offset: usize) -> Try<u32> {
+    Ok(u32::from_be_bytes(
+        data.get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated box"))?
+            .try_into()?,
+    ))
+}
+
+fn u64_be(data: &[u8], offset: usize) -> Try<u64> {
+    Ok(u64::from_be_bytes(
+        data.get(offset..offset + 8)
+            .ok_or_else(|| anyhow!("truncated box"))?
+            .try_into()?,
+    ))
+}
+
+/// Decodes the AAC audio in an MP4 file.
+pub struct AacSource {
+    reader: Box<dyn TrackReader>,
+    /// Where the reader is in the file, so we only seek when frames aren't next to each other
+    reader_position: u64,
+    samples: vec::IntoIter<Range<u64>>,
+    frame: Vec<u8>,
+    decoder: Decoder,
+    channels: u16,
+    sample_rate: u32,
+    buffer: Vec<i16>,
+    position: usize,
+    end: usize,
+}
+
+impl AacSource {
+    pub fn new(mut reader: Box<dyn TrackReader>) -> Try<Self> {
+        let file_length = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+        let track = read_audio_track(&read_moov(&mut reader)?, file_length)?;
+        let reader_position = reader.seek(SeekFrom::Current(0))?;
+        let mut decoder = Decoder::new(Transport::Raw);
+        decoder
+            .config_raw(&track.decoder_config)
+            .map_err(|e| anyhow!("invalid AAC decoder config: {:?}", e))?;
+        let mut source = AacSource {
+            reader,
+            reader_position,
+            samples: track.samples.into_iter(),
+            frame: Vec::new(),
+            decoder,
+            channels: 0,
+            sample_rate: 0,
+            buffer: vec![0; AAC_MAX_FRAME_SAMPLES],
+            position: 0,
+            end: 0,
+        };
+        // the decoder only knows the output format once it has decoded something
+        source.decode_frame()?;
+        let stream_info = source.decoder.stream_info();
+        source.channels = stream_info.numChannels as u16;
+        source.sample_rate = stream_info.sampleRate as u32;
+        Ok(source)
+    }
+
+    /// Decodes the next frame into the buffer, returning false at the end of the stream.
+    fn decode_frame(&mut self) -> Try<bool> {
+        let sample = match self.samples.next() {
+            Some(sample) => sample,
+            None => return Ok(false),
+        };
+        if sample.start != self.reader_position {
+            self.reader.seek(SeekFrom::Start(sample.start))?;
+        }
+        // the size comes from the file, so don't trust it with an allocation
+        let length = sample.end - sample.start;
+        self.frame.clear();
+        self.reader
+            .by_ref()
+            .take(length)
+            .read_to_end(&mut self.frame)?;
+        if (self.frame.len() as u64) < length {
+            return Err(anyhow!("AAC frame is outside the file"));
+        }
+        self.reader_position = sample.end;
+        self.decoder
+            .fill(&self.frame)
+            .map_err(|e| anyhow!("error buffering AAC frame: {:?}", e))?;
+        self.decoder
+            .decode_frame(&mut self.buffer)
+            .map_err(|e| anyhow!("error decoding AAC frame: {:?}", e))?;
+        self.position = 0;
+        self.end = self.decoder.decoded_frame_size();
+        Ok(true)
+    }
+}
+
+impl Iterator for AacSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.position == self.end {
+            match self.decode_frame() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    log::warn!("error decoding AAC stream, stopping: {:#}", e);
+                    return None;
+                }
+            }
+        }
+        let sample = self.buffer[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl Source for AacSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        // the format never changes within the stream
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ArtistRole;
+    use std::fs;
+    use std::io::Cursor;
+
+    /// Three fake AAC frames in the media data, followed by the metadata.
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/file_formats/fixtures/tagged.m4a"
+    );
+
+    #[test]
+    fn reads_tags_and_duration() {
+        let (track, album, artists) = read_metadata(FIXTURE.to_string()).unwrap();
+        assert_eq!(track.title, "Song Title");
+        assert_eq!(album.title, "Album Title");
+        assert_eq!(artists[0].0.name, "Artist Name");
+        assert_eq!(artists[0].1, ArtistRole::Primary);
+        assert_eq!(track.track_number, Some(3));
+        assert_eq!(track.disc_number, Some(1));
+        // the audio track's duration, rather than the slightly longer movie
+        assert_eq!(track.duration_secs, 3.0);
+    }
+
+    #[test]
+    fn reads_track_and_disc_numbers() {
+        let moov = read_moov(&mut File::open(FIXTURE).unwrap()).unwrap();
+        let tags = read_tags(&moov).unwrap();
+        assert_eq!(number_and_total(&tags[&TRACK_NUMBER].1), Some((3, 12)));
+        assert_eq!(number_and_total(&tags[&DISC_NUMBER].1), Some((1, 2)));
+    }
+
+    #[test]
+    fn reads_cover_art() {
+        let picture = read_cover_art(FIXTURE).unwrap().unwrap();
+        assert_eq!(picture.mime_type, "image/jpeg");
+        assert_eq!(picture.data, b"\xff\xd8\xff\xe0jpeg");
+    }
+
+    #[test]
+    fn huge_box_sizes_are_errors() {
+        // an empty box first, so that adding the size to the offset overflows
+        let mut moov = Vec::new();
+        moov.extend_from_slice(&8_u32.to_be_bytes());
+        moov.extend_from_slice(b"free");
+        moov.extend_from_slice(&1_u32.to_be_bytes());
+        moov.extend_from_slice(b"moov");
+        moov.extend_from_slice(&u64::max_value().to_be_bytes());
+        moov.extend_from_slice(&[0; 16]);
+        assert!(read_moov(&mut Cursor::new(&moov)).is_err());
+        assert!(atoms(&moov).is_err());
+    }
+
+    #[test]
+    fn finds_frames_across_chunks() {
+        let data = fs::read(FIXTURE).unwrap();
+        assert!(is_mp4(&data));
+        let moov = read_moov(&mut Cursor::new(&data)).unwrap();
+        let track = read_audio_track(&moov, data.len() as u64).unwrap();
+        // AAC LC, 44.1kHz, stereo
+        assert_eq!(track.decoder_config, vec![0x12, 0x10]);
+        assert_eq!(track.samples, vec![36..41, 41..47, 47..54]);
+        assert_eq!(&data[47..54], &[3; 7]);
+    }
+
+    #[test]
+    fn huge_sample_counts_are_errors() {
+        let data = fs::read(FIXTURE).unwrap();
+        let stsz = data.windows(4).position(|w| w == b"stsz").unwrap() + 4;
+        for uniform_size in &[0_u32, 1] {
+            let mut forged = data.clone();
+            forged[stsz + 4..stsz + 8].copy_from_slice(&uniform_size.to_be_bytes());
+            forged[stsz + 8..stsz + 12].copy_from_slice(&u32::max_value().to_be_bytes());
+            let moov = read_moov(&mut Cursor::new(&forged)).unwrap();
+            assert!(read_audio_track(&moov, forged.len() as u64).is_err());
+        }
+    }
+}
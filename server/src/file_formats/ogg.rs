@@ -0,0 +1,305 @@
+use super::{choose_cover, parse_picture_block, Metadata, Picture, Tags};
+use crate::errors::Try;
+use crate::model::TrackReader;
+use ogg::PacketReader;
+use rodio::Source;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::time::Duration;
+
+/// Opus always decodes at 48kHz, whatever the rate of the original audio was.
+const OPUS_SAMPLE_RATE: u32 = 48000;
+/// Longest Opus packet is 120ms, which is this many samples per channel.
+const OPUS_MAX_FRAME_SAMPLES: usize = 5760;
+/// How far from the end of the file we look for the last page, which holds the total length.
+const LAST_PAGE_SEARCH_BYTES: u64 = 64 * 1024;
+
+enum Codec {
+    Vorbis { sample_rate: u32 },
+    Opus { channels: u8, pre_skip: u16 },
+}
+
+/// Reads the metadata of an Ogg Vorbis or Ogg Opus file.
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
+    let (codec, comments) = read_headers(&file_path)?;
+    let tags = Tags::from_vorbis_comments(|name| comments.get(name).cloned().unwrap_or_default());
+    let last_granule_position = read_last_granule_position(&file_path)?;
+    let duration_secs = match codec {
+        Codec::Vorbis { sample_rate } => last_granule_position as f32 / sample_rate as f32,
+        // granule positions include the samples the decoder throws away at the start
+        Codec::Opus { pre_skip, .. } => {
+            last_granule_position.saturating_sub(u64::from(pre_skip)) as f32
+                / OPUS_SAMPLE_RATE as f32
+        }
+    };
+    Ok(tags.into_metadata(file_path, duration_secs))
+}
+
+/// Reads the front cover embedded in an Ogg file, or else the first picture there is.
+pub fn read_cover_art(file_path: &str) -> Try<Option<Picture>> {
+    let (_, comments) = read_headers(file_path)?;
+    let mut pictures = Vec::new();
+    for encoded in comments.get("METADATA_BLOCK_PICTURE").into_iter().flatten() {
+        match base64::decode(encoded)
+            .map_err(anyhow::Error::from)
+            .and_then(|block| parse_picture_block(&block))
+        {
+            Ok(picture) => pictures.push(picture),
+            Err(e) => log::warn!("invalid picture in {}: {:#}", file_path, e),
+        }
+    }
+    Ok(choose_cover(pictures))
+}
+
+/// Returns true iff the data is an Ogg stream containing Opus, which rodio can't decode.
+pub fn is_opus(data: &[u8]) -> bool {
+    // the identification header is alone on the first page, after the header and segment table
+    if data.len() < 27 || &data[..4] != b"OggS" {
+        return false;
+    }
+    let payload_start = 27 + data[26] as usize;
+    data.get(payload_start..payload_start + 8) == Some(&b"OpusHead"[..])
+}
+
+/// Reads the identification and comment headers, which begin every Ogg Vorbis or Opus stream.
+fn read_headers(file_path: &str) -> Try<(Codec, HashMap<String, Vec<String>>)> {
+    let mut packets = PacketReader::new(BufReader::new(File::open(file_path)?));
+    let ident = packets.read_packet_expected()?.data;
+    let comments = packets.read_packet_expected()?.data;
+    let (codec, comments) = if ident.starts_with(b"\x01vorbis") {
+        let codec = Codec::Vorbis {
+            sample_rate: u32_le(&ident, 12)?,
+        };
+        if !comments.starts_with(b"\x03vorbis") {
+            return Err(anyhow!("missing Vorbis comment header in {}", file_path));
+        }
+        (codec, &comments[7..])
+    } else if ident.starts_with(b"OpusHead") {
+        let codec = parse_opus_head(&ident)?;
+        if !comments.starts_with(b"OpusTags") {
+            return Err(anyhow!("missing Opus tags header in {}", file_path));
+        }
+        (codec, &comments[8..])
+    } else {
+        return Err(anyhow!("{} is neither Ogg Vorbis nor Ogg Opus", file_path));
+    };
+    Ok((codec, parse_comments(comments)?))
+}
+
+fn parse_opus_head(ident: &[u8]) -> Try<Codec> {
+    Ok(Codec::Opus {
+        channels: *ident
+            .get(9)
+            .ok_or_else(|| anyhow!("truncated Opus header"))?,
+        pre_skip: u16::from_le_bytes(
+            ident
+                .get(10..12)
+                .ok_or_else(|| anyhow!("truncated Opus header"))?
+                .try_into()?,
+        ),
+    })
+}
+
+/// Parses a Vorbis comment block into a map from upper case field names to their values.
+fn parse_comments(data: &[u8]) -> Try<HashMap<String, Vec<String>>> {
+    let vendor_length = u32_le(data, 0)? as usize;
+    let mut offset = 4 + vendor_length;
+    let count = u32_le(data, offset)?;
+    offset += 4;
+    let mut comments: HashMap<String, Vec<String>> = HashMap::new();
+    for _ in 0..count {
+        let length = u32_le(data, offset)? as usize;
+        offset += 4;
+        let comment = data
+            .get(offset..offset + length)
+            .ok_or_else(|| anyhow!("truncated comment header"))?;
+        offset += length;
+        let comment = String::from_utf8_lossy(comment);
+        match comment.find('=') {
+            Some(equals) => comments
+                .entry(comment[..equals].to_ascii_uppercase())
+                .or_default()
+                .push(comment[equals + 1..].to_string()),
+            None => log::warn!("ignoring comment without a field name: {}", comment),
+        }
+    }
+    Ok(comments)
+}
+
+/// Finds the granule position of the last page, which is the number of samples in the stream.
+fn read_last_granule_position(file_path: &str) -> Try<u64> {
+    let mut file = File::open(file_path)?;
+    let length = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(
+        length.saturating_sub(LAST_PAGE_SEARCH_BYTES),
+    ))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    for page_start in (0..tail.len().saturating_sub(13)).rev() {
+        // pages start with a capture pattern and a zero version byte
+        if &tail[page_start..page_start + 4] != b"OggS" || tail[page_start + 4] != 0 {
+            continue;
+        }
+        let granule_position =
+            u64::from_le_bytes(tail[page_start + 6..page_start + 14].try_into()?);
+        // pages where no packet finishes have no granule position
+        if granule_position != u64::max_value() {
+            return Ok(granule_position);
+        }
+    }
+    Err(anyhow!(
+        "no pages with a granule position at the end of {}",
+        file_path
+    ))
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Try<u32> {
+    Ok(u32::from_le_bytes(
+        data.get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated header"))?
+            .try_into()?,
+    ))
+}
+
+/// Decodes an Ogg Opus stream.
+pub struct OpusSource {
+    packets: PacketReader<Box<dyn TrackReader>>,
+    decoder: opus::Decoder,
+    channels: u16,
+    /// Samples still to be discarded from the start of the stream
+    pre_skip: usize,
+    buffer: Vec<i16>,
+    position: usize,
+    end: usize,
+}
+
+impl OpusSource {
+    pub fn new(reader: Box<dyn TrackReader>) -> Try<Self> {
+        let mut packets = PacketReader::new(reader);
+        let (channels, pre_skip) = match parse_opus_head(&packets.read_packet_expected()?.data)? {
+            Codec::Opus { channels, pre_skip } => (channels, pre_skip),
+            Codec::Vorbis { .. } => unreachable!(),
+        };
+        // the tags are of no use to playback
+        packets.read_packet_expected()?;
+        let decoder = opus::Decoder::new(
+            OPUS_SAMPLE_RATE,
+            match channels {
+                1 => opus::Channels::Mono,
+                2 => opus::Channels::Stereo,
+                _ => {
+                    return Err(anyhow!(
+                        "Opus streams with {} channels are not supported",
+                        channels
+                    ))
+                }
+            },
+        )?;
+        Ok(OpusSource {
+            packets,
+            decoder,
+            channels: channels.into(),
+            pre_skip: usize::from(pre_skip) * usize::from(channels),
+            buffer: vec![0; OPUS_MAX_FRAME_SAMPLES * usize::from(channels)],
+            position: 0,
+            end: 0,
+        })
+    }
+
+    /// Decodes the next packet into the buffer, returning false at the end of the stream.
+    fn decode_packet(&mut self) -> Try<bool> {
+        let packet = match self.packets.read_packet()? {
+            Some(packet) => packet,
+            None => return Ok(false),
+        };
+        let samples_per_channel = self.decoder.decode(&packet.data, &mut self.buffer, false)?;
+        self.end = samples_per_channel * usize::from(self.channels);
+        self.position = self.pre_skip.min(self.end);
+        self.pre_skip -= self.position;
+        Ok(true)
+    }
+}
+
+impl Iterator for OpusSource {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<i16> {
+        while self.position == self.end {
+            match self.decode_packet() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    log::warn!("error decoding Opus stream, stopping: {:#}", e);
+                    return None;
+                }
+            }
+        }
+        let sample = self.buffer[self.position];
+        self.position += 1;
+        Some(sample)
+    }
+}
+
+impl Source for OpusSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        // the format never changes within the stream
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        OPUS_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment_block(comments: &[&str]) -> Vec<u8> {
+        let mut block = Vec::new();
+        block.extend_from_slice(&6_u32.to_le_bytes());
+        block.extend_from_slice(b"vendor");
+        block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            block.extend_from_slice(comment.as_bytes());
+        }
+        block
+    }
+
+    #[test]
+    fn comment_names_are_case_insensitive_and_may_repeat() {
+        let comments =
+            parse_comments(&comment_block(&["title=Song", "ARTIST=A", "Artist=B=C"])).unwrap();
+        assert_eq!(comments["TITLE"], vec!["Song"]);
+        assert_eq!(comments["ARTIST"], vec!["A", "B=C"]);
+    }
+
+    #[test]
+    fn truncated_comments_are_an_error() {
+        let block = comment_block(&["TITLE=Song"]);
+        assert!(parse_comments(&block[..block.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn recognises_opus_streams() {
+        let mut page = b"OggS\0\x02".to_vec();
+        page.extend_from_slice(&[0; 20]);
+        page.push(1);
+        page.push(19);
+        page.extend_from_slice(b"OpusHead");
+        assert!(is_opus(&page));
+        page[28..36].copy_from_slice(b"\x01vorbis\0");
+        assert!(!is_opus(&page));
+    }
+}
@@ -0,0 +1,171 @@
+use super::{parse_number, Metadata, Tags};
+use crate::errors::Try;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+
+/// Reads the metadata of a WAV file, from a RIFF INFO list or an embedded ID3 tag.
+pub fn read_metadata(file_path: String) -> Try<Metadata> {
+    let mut file = BufReader::new(File::open(&file_path)?);
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+    if &header[..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(anyhow!("{} is not a WAV file", file_path));
+    }
+
+    let mut bytes_per_sec = None;
+    let mut data_length = None;
+    let mut info = HashMap::new();
+    let mut id3_tag = None;
+    loop {
+        let mut chunk_header = [0; 8];
+        if file.read_exact(&mut chunk_header).is_err() {
+            break;
+        }
+        let length = u32::from_le_bytes(chunk_header[4..8].try_into()?);
+        match &chunk_header[..4] {
+            b"fmt " => {
+                let fmt = read_chunk(&mut file, length)?;
+                // channels * sample rate * bytes per sample, as long as the audio is uncompressed
+                bytes_per_sec = Some(u32_le(&fmt, 8)?);
+            }
+            b"data" => {
+                data_length = Some(length);
+                skip_chunk(&mut file, length)?;
+            }
+            b"LIST" => {
+                let list = read_chunk(&mut file, length)?;
+                if list.starts_with(b"INFO") {
+                    info = parse_info(&list[4..])?;
+                }
+            }
+            b"id3 " | b"ID3 " => {
+                let tag = read_chunk(&mut file, length)?;
+                match id3::Tag::read_from(&mut Cursor::new(&tag[..])) {
+                    Ok(tag) => id3_tag = Some(tag),
+                    Err(e) => log::warn!("invalid ID3 tag in {}: {}", file_path, e),
+                }
+            }
+            _ => skip_chunk(&mut file, length)?,
+        }
+    }
+
+    let duration_secs = match (bytes_per_sec, data_length) {
+        (Some(bytes_per_sec), Some(data_length)) if bytes_per_sec > 0 => {
+            data_length as f32 / bytes_per_sec as f32
+        }
+        _ => return Err(anyhow!("no audio format or data in {}", file_path)),
+    };
+    // an ID3 tag is richer than the INFO list, so prefer what it says
+    let mut tags = id3_tag.as_ref().map(Tags::from_id3).unwrap_or_default();
+    tags.title = tags.title.or_else(|| info.remove(b"INAM"));
+    tags.album = tags.album.or_else(|| info.remove(b"IPRD"));
+    if tags.artists.is_empty() {
+        tags.artists.extend(info.remove(b"IART"));
+    }
+    tags.genre = tags.genre.or_else(|| info.remove(b"IGNR"));
+    tags.date = tags.date.or_else(|| info.remove(b"ICRD"));
+    tags.track_number = tags
+        .track_number
+        .or_else(|| info.remove(b"ITRK").and_then(|n| parse_number(&n)));
+    Ok(tags.into_metadata(file_path, duration_secs))
+}
+
+/// Parses the text fields of a RIFF INFO list, which are null terminated.
+fn parse_info(data: &[u8]) -> Try<HashMap<[u8; 4], String>> {
+    let mut fields = HashMap::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let id: [u8; 4] = data[offset..offset + 4].try_into()?;
+        let length = u32_le(data, offset + 4)? as usize;
+        let value = data
+            .get(offset + 8..offset + 8 + length)
+            .ok_or_else(|| anyhow!("truncated INFO list"))?;
+        let value = String::from_utf8_lossy(value)
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        if !value.is_empty() {
+            fields.insert(id, value);
+        }
+        // chunks are padded to an even length
+        offset += 8 + length + length % 2;
+    }
+    Ok(fields)
+}
+
+fn read_chunk(file: &mut impl Read, length: u32) -> Try<Vec<u8>> {
+    // the length comes from the file, so don't trust it with an allocation
+    let mut chunk = Vec::new();
+    file.by_ref()
+        .take(u64::from(length))
+        .read_to_end(&mut chunk)?;
+    if chunk.len() < length as usize {
+        return Err(anyhow!("truncated chunk"));
+    }
+    if length % 2 == 1 {
+        file.read_exact(&mut [0])?;
+    }
+    Ok(chunk)
+}
+
+fn skip_chunk(file: &mut impl Seek, length: u32) -> Try<()> {
+    file.seek(SeekFrom::Current(i64::from(length) + i64::from(length % 2)))?;
+    Ok(())
+}
+
+fn u32_le(data: &[u8], offset: usize) -> Try<u32> {
+    Ok(u32::from_le_bytes(
+        data.get(offset..offset + 4)
+            .ok_or_else(|| anyhow!("truncated chunk"))?
+            .try_into()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untagged_files_are_named_after_the_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("07 - Untagged Song.wav");
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&file_path, spec).unwrap();
+        for _ in 0..2 * 44100 * 3 / 2 {
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let (track, album, _) = read_metadata(file_path.to_str().unwrap().to_string()).unwrap();
+        assert_eq!(track.title, "Untagged Song");
+        assert_eq!(album.title, "UNKNOWN ALBUM");
+        assert_eq!(track.duration_secs, 1.5);
+    }
+
+    #[test]
+    fn huge_chunk_lengths_are_errors() {
+        let mut file = Cursor::new(vec![0; 8]);
+        assert!(read_chunk(&mut file, u32::max_value()).is_err());
+    }
+
+    #[test]
+    fn parses_info_fields() {
+        let mut list = Vec::new();
+        list.extend_from_slice(b"INAM");
+        list.extend_from_slice(&5_u32.to_le_bytes());
+        list.extend_from_slice(b"Song\0\0");
+        list.extend_from_slice(b"IART");
+        list.extend_from_slice(&7_u32.to_le_bytes());
+        list.extend_from_slice(b"Artist\0");
+        let fields = parse_info(&list).unwrap();
+        assert_eq!(fields[b"INAM"], "Song");
+        assert_eq!(fields[b"IART"], "Artist");
+    }
+}
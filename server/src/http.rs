@@ -1,6 +1,8 @@
 use crate::api;
+use crate::art::ArtCache;
+use crate::errors::Try;
 use std::sync::Arc;
-use warp::http::header::CONTENT_TYPE;
+use warp::http::header::{CACHE_CONTROL, CONTENT_TYPE};
 use warp::http::status::StatusCode;
 use warp::http::Response;
 use warp::Reply;
@@ -9,6 +11,38 @@ pub fn api_handler(app: Arc<api::App>, request: api::Request) -> impl Reply {
     to_http_response(app.handle_request(&request))
 }
 
+pub fn art_handler(art_cache: &ArtCache, hash: &str) -> impl Reply {
+    to_image_response(art_cache.get(hash))
+}
+
+pub fn thumbnail_handler(art_cache: &ArtCache, hash: &str) -> impl Reply {
+    to_image_response(
+        art_cache
+            .get_thumbnail(hash)
+            .map(|t| t.map(|thumbnail| ("image/jpeg", thumbnail))),
+    )
+}
+
+fn to_image_response(result: Try<Option<(&'static str, Vec<u8>)>>) -> impl Reply {
+    match result {
+        Ok(Some((mime_type, data))) => Response::builder()
+            .header(CONTENT_TYPE, mime_type)
+            // art is named after its contents, so it never changes
+            .header(CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .status(StatusCode::OK)
+            .body(data),
+        Ok(None) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Vec::new()),
+        Err(e) => {
+            log::error!("failed to serve art: {:#}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Vec::new())
+        }
+    }
+}
+
 fn to_http_response(result: api::Response) -> impl Reply {
     let (status, body) = match result {
         Ok(p) => (StatusCode::OK, p.json),
@@ -2,7 +2,9 @@
 // TODO: enable pedantic
 
 pub mod api;
+mod art;
 mod bootstrap;
+pub mod config;
 pub mod errors;
 mod file_completions;
 mod file_formats;
@@ -10,12 +12,14 @@ mod http;
 pub mod ids;
 mod library;
 pub mod model;
-mod playback;
+pub mod playback;
 mod player;
+mod preroll;
 mod queue;
 pub mod serde;
 pub mod server;
 pub mod services;
+mod watcher;
 mod websocket;
 #[macro_use]
 extern crate diesel;
@@ -1,41 +1,112 @@
 use super::schema::{
     albums, artists, external_albums, external_artists, external_tracks, playlist_tracks,
-    playlists, tracks,
+    playlists, track_artists, tracks,
 };
 use super::tables;
-use crate::api::search::SearchResults;
+use crate::api::search::{SearchResult, SearchResults, TrackSearchResult};
 use crate::api::Event;
 use crate::api::EventSink;
+use crate::art::ArtCache;
 use crate::errors::Try;
 use crate::file_formats;
-use crate::ids::{Album, Artist, ExternalId, IdString, LibraryId, Track};
-use crate::library::{Playlist, TrackSummary};
-use crate::model::{AlbumInfo, ArtistInfo, TrackInfo};
+use crate::file_formats::Metadata;
+use crate::ids::{Album, Artist, Entity, ExternalId, IdString, LibraryId, Track};
+use crate::library::{ArtistCredit, Playlist, TrackSummary};
+use crate::model::{AlbumInfo, ArtistInfo, ArtistRole, TrackInfo};
 use crate::services::ServiceId;
 use diesel::dsl::exists;
 use diesel::prelude::*;
 use diesel::query_builder::QueryFragment;
 use diesel::sqlite::{Sqlite, SqliteConnection};
-use diesel::{debug_query, insert_into, select, sql_types};
-use std::collections::HashMap;
+use diesel::{debug_query, delete, insert_into, select, sql_query, sql_types, update};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::MAIN_SEPARATOR;
 use std::sync::Arc;
 use thread_local::CachedThreadLocal;
 
+/// How many of each kind of result a search of the library returns.
+const SEARCH_LIMIT: i64 = 20;
+
 pub struct Library {
     connection: CachedThreadLocal<SqliteConnection>,
     file_path: String,
     event_sink: Arc<EventSink>,
+    /// Where to keep cover art read from local files, if we read it at all
+    art_cache: Option<Arc<ArtCache>>,
 }
 
 impl Library {
+    /// Returns every track, grouped by album and in album order.
     pub fn tracks(&self) -> Try<impl Iterator<Item = TrackSummary>> {
         let rows: Vec<(tables::Track, tables::Album, tables::Artist)> = tracks::table
             .inner_join(albums::table)
             .inner_join(artists::table)
+            .order((
+                albums::title,
+                tracks::album_id,
+                tracks::disc_number,
+                tracks::track_number,
+                tracks::title,
+            ))
+            .log()
+            .load(self.connection()?)?;
+        let mut credits = self.load_credits(None)?;
+        // TODO: external IDs
+        Ok(rows.into_iter().map(move |row| {
+            let track_credits = credits.remove(&row.0.track_id.unwrap());
+            into_track(row, vec![], track_credits.unwrap_or_default())
+        }))
+    }
+
+    /// Returns the tracks on an album, ordered by disc and track number.
+    pub fn album_tracks(&self, album_id: LibraryId<Album>) -> Try<Vec<TrackSummary>> {
+        let rows: Vec<(tables::Track, tables::Album, tables::Artist)> = tracks::table
+            .filter(tracks::album_id.eq(album_id.0))
+            .inner_join(albums::table)
+            .inner_join(artists::table)
+            .order((tracks::disc_number, tracks::track_number, tracks::title))
             .log()
             .load(self.connection()?)?;
+        let track_ids: Vec<i64> = rows.iter().map(|(t, _, _)| t.track_id.unwrap()).collect();
+        let mut credits = self.load_credits(Some(&track_ids))?;
         // TODO: external IDs
-        Ok(rows.into_iter().map(|row| into_track(row, vec![])))
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let track_credits = credits.remove(&row.0.track_id.unwrap());
+                into_track(row, vec![], track_credits.unwrap_or_default())
+            })
+            .collect())
+    }
+
+    /// Returns the tracks an artist is a primary artist on, album by album in order of release.
+    pub fn artist_track_ids(&self, artist_id: LibraryId<Artist>) -> Try<Vec<LibraryId<Track>>> {
+        let credited = track_artists::table
+            .filter(track_artists::artist_id.eq(artist_id.0))
+            .filter(track_artists::role.eq(ArtistRole::Primary.to_string()))
+            .select(track_artists::track_id);
+        let track_ids: Vec<Option<i64>> = tracks::table
+            .inner_join(albums::table)
+            .filter(tracks::track_id.eq_any(credited))
+            .filter(tracks::missing.eq(false))
+            .select(tracks::track_id)
+            .order((
+                albums::year,
+                albums::release_date,
+                albums::title,
+                tracks::album_id,
+                tracks::disc_number,
+                tracks::track_number,
+                tracks::title,
+            ))
+            .log()
+            .load(self.connection()?)?;
+        Ok(track_ids
+            .into_iter()
+            .flatten()
+            .map(LibraryId::new)
+            .collect())
     }
 
     pub fn get_track(&self, id: LibraryId<Track>) -> Try<Option<TrackSummary>> {
@@ -51,20 +122,50 @@ impl Library {
                 .filter(external_tracks::track_id.eq(id.0))
                 .log()
                 .load(self.connection()?)?;
-            Some(into_track(row, external_ids))
+            let credits = self.load_credits(Some(&[id.0]))?.remove(&id.0);
+            Some(into_track(row, external_ids, credits.unwrap_or_default()))
         } else {
             None
         })
     }
 
+    /// Returns the artists credited on the given tracks, or on every track, in credit order.
+    fn load_credits(&self, track_ids: Option<&[i64]>) -> Try<HashMap<i64, Vec<ArtistCredit>>> {
+        let mut query = track_artists::table
+            .inner_join(artists::table)
+            .order((track_artists::track_id, track_artists::position))
+            .into_boxed::<Sqlite>();
+        if let Some(track_ids) = track_ids {
+            query = query.filter(track_artists::track_id.eq_any(track_ids));
+        }
+        let rows: Vec<(tables::TrackArtist, tables::Artist)> =
+            query.log().load(self.connection()?)?;
+        let mut credits: HashMap<i64, Vec<ArtistCredit>> = HashMap::new();
+        for (credit, artist) in rows {
+            let (artist_id, artist_info) = into_artist(artist);
+            credits
+                .entry(credit.track_id)
+                .or_default()
+                .push(ArtistCredit {
+                    artist_id,
+                    artist_info,
+                    role: credit.role.parse()?,
+                });
+        }
+        Ok(credits)
+    }
+
+    /// Adds a track to the library, credited to the given artists. The first primary artist is
+    /// the one the track is listed under.
     // TODO: special error enum including track/playlist/artist/album not found
     pub fn create_track(
         &self,
         track: TrackInfo,
         album: LibraryId<Album>,
-        artist: LibraryId<Artist>,
+        artists: &[(LibraryId<Artist>, ArtistRole)],
         external_id: Option<ExternalId<Track>>,
     ) -> Try<LibraryId<Track>> {
+        let artist = primary_artist(artists)?;
         let id = self.in_transaction(|c| {
             insert_into(tracks::table)
                 .values(tables::Track {
@@ -75,10 +176,16 @@ impl Library {
                     isrc: track.isrc,
                     duration_secs: track.duration_secs,
                     file_path: track.file_path,
+                    missing: false,
+                    track_number: track.track_number.map(|n| n as i32),
+                    disc_number: track.disc_number.map(|n| n as i32),
+                    genre: track.genre,
+                    composer: track.composer,
                 })
                 .log()
                 .execute(c)?;
             let track_id = last_id(c)?;
+            insert_credits(c, track_id, artists)?;
             if let Some(external_id) = external_id {
                 insert_into(external_tracks::table)
                     .values(tables::ExternalTrack {
@@ -114,8 +221,11 @@ impl Library {
                 .values(tables::Album {
                     album_id: None,
                     title: album.title,
-                    cover_image_url: album.cover_image_url.map(|u| u.into_string()),
+                    cover_image_url: album.cover_image_url,
                     release_date: album.release_date.map(|d| d.to_string()),
+                    album_artist: album.album_artist,
+                    year: album.year,
+                    musicbrainz_id: album.musicbrainz_id,
                 })
                 .log()
                 .execute(c)?;
@@ -143,6 +253,29 @@ impl Library {
         Ok(albums.into_iter().map(into_album).collect())
     }
 
+    /// Finds the album that an album read from a file's tags is already in the library as: the
+    /// album with the same MusicBrainz release ID, or else the album with the same title and album
+    /// artist.
+    pub fn find_local_album(&self, album: &AlbumInfo) -> Try<Option<LibraryId<Album>>> {
+        if let Some(musicbrainz_id) = &album.musicbrainz_id {
+            let album_id = albums::table
+                .select(albums::album_id)
+                .filter(albums::musicbrainz_id.eq(musicbrainz_id))
+                .log()
+                .first::<Option<i64>>(self.connection()?)
+                .optional()?
+                .flatten();
+            if let Some(album_id) = album_id {
+                return Ok(Some(LibraryId::new(album_id)));
+            }
+        }
+        Ok(self
+            .find_albums_by_name(&album.title)?
+            .into_iter()
+            .find(|(_, existing)| same_album(existing, album))
+            .map(|(id, _)| id))
+    }
+
     pub fn find_external_album(
         &self,
         external_id: &ExternalId<Album>,
@@ -157,10 +290,26 @@ impl Library {
         Ok(album.map(|(_, a)| into_album(a)))
     }
 
-    pub fn artists(&self) -> Try<impl Iterator<Item = (LibraryId<Artist>, ArtistInfo)>> {
+    /// Returns every artist, with each role they are credited in on any track.
+    pub fn artists(
+        &self,
+    ) -> Try<impl Iterator<Item = (LibraryId<Artist>, ArtistInfo, Vec<ArtistRole>)>> {
         // TODO: how can I log this? Table doesn't implement QueryFragment
         let rows: Vec<(tables::Artist)> = artists::table.load(self.connection()?)?;
-        Ok(rows.into_iter().map(|row| into_artist(row)))
+        let credits: Vec<(i64, String)> = track_artists::table
+            .select((track_artists::artist_id, track_artists::role))
+            .distinct()
+            .log()
+            .load(self.connection()?)?;
+        let mut roles: HashMap<i64, Vec<ArtistRole>> = HashMap::new();
+        for (artist_id, role) in credits {
+            roles.entry(artist_id).or_default().push(role.parse()?);
+        }
+        Ok(rows.into_iter().map(move |row| {
+            let artist_roles = roles.remove(&row.artist_id.unwrap());
+            let (id, info) = into_artist(row);
+            (id, info, artist_roles.unwrap_or_default())
+        }))
     }
 
     pub fn create_artist(
@@ -215,26 +364,24 @@ impl Library {
         Ok(artist.map(|(_, a)| into_artist(a)))
     }
 
+    /// Returns the paths of all the files in the library.
+    pub fn local_file_paths(&self) -> Try<HashSet<String>> {
+        let file_paths: Vec<Option<String>> = tracks::table
+            .select(tracks::file_path)
+            .filter(tracks::file_path.is_not_null())
+            .log()
+            .load(self.connection()?)?;
+        Ok(file_paths.into_iter().flatten().collect())
+    }
+
     pub fn playlists(&self) -> Try<impl Iterator<Item = Playlist>> {
-        let rows: Vec<(tables::Playlist, tables::PlaylistTrack)> = playlists::table
-            .inner_join(playlist_tracks::table)
+        // a left join, so that playlists without any tracks are still there
+        let rows: Vec<PlaylistRow> = playlists::table
+            .left_join(playlist_tracks::table.inner_join(tracks::table))
+            .order((playlists::playlist_id, playlist_tracks::position))
             .log()
             .load(self.connection()?)?;
-        let mut name_and_track_ids_by_playlist_id = HashMap::new();
-        for (playlist, playlist_track) in rows {
-            name_and_track_ids_by_playlist_id
-                .entry(LibraryId::new(playlist_track.playlist_id))
-                .or_insert((playlist.name, Vec::new()))
-                .1
-                .push(LibraryId::new(playlist_track.track_id))
-        }
-        Ok(name_and_track_ids_by_playlist_id
-            .into_iter()
-            .map(|(id, (name, track_ids))| Playlist {
-                id,
-                name,
-                track_ids,
-            }))
+        Ok(into_playlists(rows).into_iter())
     }
 
     pub fn create_playlist(&self, name: String) -> Try<LibraryId<crate::ids::Playlist>> {
@@ -242,34 +389,55 @@ impl Library {
         insert_into(playlists::table)
             .values(tables::Playlist {
                 playlist_id: None,
-                name,
+                name: name.clone(),
             })
             .log()
             .execute(c)?;
-        let id = last_id(c)?;
-        Ok(LibraryId::new(id))
+        let playlist_id = LibraryId::new(last_id(c)?);
+        self.event_sink
+            .broadcast(&Event::PlaylistCreated { playlist_id, name });
+        Ok(playlist_id)
+    }
+
+    pub fn rename_playlist(&self, id: LibraryId<crate::ids::Playlist>, name: String) -> Try<()> {
+        let updated = update(playlists::table.find(id.0))
+            .set(playlists::name.eq(&name))
+            .log()
+            .execute(self.connection()?)?;
+        if updated == 0 {
+            return Err(anyhow!("no playlist {}", id.0));
+        }
+        self.event_sink.broadcast(&Event::PlaylistRenamed {
+            playlist_id: id,
+            name,
+        });
+        Ok(())
+    }
+
+    pub fn delete_playlist(&self, id: LibraryId<crate::ids::Playlist>) -> Try<()> {
+        self.in_transaction(|c| {
+            delete(playlist_tracks::table.filter(playlist_tracks::playlist_id.eq(id.0)))
+                .log()
+                .execute(c)?;
+            let deleted = delete(playlists::table.find(id.0)).log().execute(c)?;
+            if deleted == 0 {
+                return Err(anyhow!("no playlist {}", id.0));
+            }
+            Ok(())
+        })?;
+        self.event_sink
+            .broadcast(&Event::PlaylistDeleted { playlist_id: id });
+        Ok(())
     }
 
     pub fn get_playlist(&self, id: LibraryId<crate::ids::Playlist>) -> Try<Option<Playlist>> {
-        let rows: Vec<(tables::Playlist, tables::PlaylistTrack)> = playlists::table
+        let rows: Vec<PlaylistRow> = playlists::table
             .find(id.0)
-            .inner_join(playlist_tracks::table)
+            .left_join(playlist_tracks::table.inner_join(tracks::table))
+            .order(playlist_tracks::position)
             .log()
             .load(self.connection()?)?;
-        Ok(if rows.is_empty() {
-            None
-        } else {
-            let track_ids = rows
-                .iter()
-                .map(|(_, t)| LibraryId::new(t.track_id))
-                .collect();
-            let (tables::Playlist { playlist_id, name }, _) = rows.into_iter().nth(0).unwrap();
-            Some(Playlist {
-                id: LibraryId::new(playlist_id.unwrap()),
-                name,
-                track_ids,
-            })
-        })
+        Ok(into_playlists(rows).pop())
     }
 
     pub fn playlist_exists(&self, id: LibraryId<crate::ids::Playlist>) -> Try<bool> {
@@ -278,38 +446,542 @@ impl Library {
             .get_result(self.connection()?)?)
     }
 
+    /// Inserts a track into a playlist before the entry at the index, or at the end if there's no
+    /// index.
     pub fn add_track_to_playlist(
         &self,
         track_id: LibraryId<Track>,
         playlist_id: LibraryId<crate::ids::Playlist>,
+        index: Option<usize>,
     ) -> Try<()> {
-        let c = self.connection()?;
-        insert_into(playlist_tracks::table)
-            .values(tables::PlaylistTrack {
-                _id: None,
-                playlist_id: playlist_id.0,
-                track_id: track_id.0,
-            })
+        let index = self.in_transaction(|c| {
+            let length = self.playlist_length(c, playlist_id)?;
+            let index = index.unwrap_or(length);
+            if index > length {
+                return Err(anyhow!(
+                    "can't insert at {} in playlist {} of {} tracks",
+                    index,
+                    playlist_id.0,
+                    length
+                ));
+            }
+            update(
+                playlist_tracks::table
+                    .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+                    .filter(playlist_tracks::position.ge(index as i32)),
+            )
+            .set(playlist_tracks::position.eq(playlist_tracks::position + 1))
             .log()
             .execute(c)?;
-        // TODO: what if track id or playlist id don't exist (causes foreign key error)
+            // TODO: what if track id doesn't exist (causes foreign key error)
+            insert_into(playlist_tracks::table)
+                .values(tables::PlaylistTrack {
+                    _id: None,
+                    playlist_id: playlist_id.0,
+                    track_id: track_id.0,
+                    position: index as i32,
+                })
+                .log()
+                .execute(c)?;
+            Ok(index)
+        })?;
         self.event_sink.broadcast(&Event::TrackAddedToPlaylist {
             track_id,
             playlist_id,
+            index,
+        });
+        Ok(())
+    }
+
+    /// Moves the entry at one index of a playlist to another, shifting the entries in between.
+    pub fn move_playlist_track(
+        &self,
+        playlist_id: LibraryId<crate::ids::Playlist>,
+        from: usize,
+        to: usize,
+    ) -> Try<()> {
+        self.in_transaction(|c| {
+            let length = self.playlist_length(c, playlist_id)?;
+            if from >= length || to >= length {
+                return Err(anyhow!(
+                    "can't move from {} to {} in playlist {} of {} tracks",
+                    from,
+                    to,
+                    playlist_id.0,
+                    length
+                ));
+            }
+            let entry = playlist_tracks::table
+                .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+                .filter(playlist_tracks::position.eq(from as i32))
+                .select(playlist_tracks::_id)
+                .log()
+                .first::<Option<i64>>(c)?;
+            let between = playlist_tracks::table
+                .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+                .filter(
+                    playlist_tracks::position.between(from.min(to) as i32, from.max(to) as i32),
+                );
+            let shift = if from < to { -1 } else { 1 };
+            update(between)
+                .set(playlist_tracks::position.eq(playlist_tracks::position + shift))
+                .log()
+                .execute(c)?;
+            update(playlist_tracks::table.filter(playlist_tracks::_id.eq(entry)))
+                .set(playlist_tracks::position.eq(to as i32))
+                .log()
+                .execute(c)?;
+            Ok(())
+        })?;
+        self.event_sink.broadcast(&Event::PlaylistTrackMoved {
+            playlist_id,
+            from,
+            to,
         });
         Ok(())
     }
 
+    /// Removes the entry at an index of a playlist.
+    pub fn remove_from_playlist(
+        &self,
+        playlist_id: LibraryId<crate::ids::Playlist>,
+        index: usize,
+    ) -> Try<()> {
+        self.in_transaction(|c| {
+            let deleted = delete(
+                playlist_tracks::table
+                    .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+                    .filter(playlist_tracks::position.eq(index as i32)),
+            )
+            .log()
+            .execute(c)?;
+            if deleted == 0 {
+                return Err(anyhow!("no entry {} in playlist {}", index, playlist_id.0));
+            }
+            update(
+                playlist_tracks::table
+                    .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+                    .filter(playlist_tracks::position.gt(index as i32)),
+            )
+            .set(playlist_tracks::position.eq(playlist_tracks::position - 1))
+            .log()
+            .execute(c)?;
+            Ok(())
+        })?;
+        self.event_sink
+            .broadcast(&Event::TrackRemovedFromPlaylist { playlist_id, index });
+        Ok(())
+    }
+
+    /// Returns the number of entries in a playlist, and fails if there's no such playlist.
+    fn playlist_length(
+        &self,
+        c: &SqliteConnection,
+        playlist_id: LibraryId<crate::ids::Playlist>,
+    ) -> Try<usize> {
+        if !self.playlist_exists(playlist_id)? {
+            return Err(anyhow!("no playlist {}", playlist_id.0));
+        }
+        let length: i64 = playlist_tracks::table
+            .filter(playlist_tracks::playlist_id.eq(playlist_id.0))
+            .count()
+            .log()
+            .get_result(c)?;
+        Ok(length as usize)
+    }
+
+    /// Returns true iff the library has no tracks and no playlists, e.g. because it was just created.
+    pub fn is_empty(&self) -> Try<bool> {
+        let c = self.connection()?;
+        let has_tracks: bool = select(exists(tracks::table)).log().get_result(c)?;
+        let has_playlists: bool = select(exists(playlists::table)).log().get_result(c)?;
+        Ok(!has_tracks && !has_playlists)
+    }
+
+    /// Copies the contents of another library into this one. Albums, artists and tracks which are
+    /// already here are reused rather than duplicated, and playlists whose names are already taken
+    /// are skipped. Tracks are matched by file path, and albums and artists by their links to
+    /// services, or else by title and album artist, or name. Albums without an album artist and
+    /// artists whose name is shared by others in the other library can't be told apart like that,
+    /// so they're only reused if they hold one of the same files or have one of the same links.
+    pub fn import(&self, other: &Library) -> Try<()> {
+        let src = other.connection()?;
+        let other_albums: Vec<tables::Album> = albums::table.load(src)?;
+        let other_artists: Vec<tables::Artist> = artists::table.load(src)?;
+        let other_tracks: Vec<tables::Track> = tracks::table.load(src)?;
+        let other_track_artists: Vec<tables::TrackArtist> = track_artists::table.load(src)?;
+        let other_external_albums: Vec<tables::ExternalAlbum> = external_albums::table.load(src)?;
+        let other_external_artists: Vec<tables::ExternalArtist> =
+            external_artists::table.load(src)?;
+        let other_external_tracks: Vec<tables::ExternalTrack> = external_tracks::table.load(src)?;
+        let other_playlists: Vec<tables::Playlist> = playlists::table.load(src)?;
+        let other_playlist_tracks: Vec<tables::PlaylistTrack> = playlist_tracks::table
+            .order(playlist_tracks::_id)
+            .log()
+            .load(src)?;
+        self.in_transaction(|c| {
+            let mut album_ids = HashMap::new();
+            for album in other_albums {
+                let (old_id, album) = into_album(album);
+                let linked = linked_album(c, old_id.0, &other_external_albums)?;
+                let existing = match linked {
+                    Some(id) => Some(id),
+                    None if album.album_artist.is_some() => {
+                        self.find_local_album(&album)?.map(|id| id.0)
+                    }
+                    None => album_holding_files(c, old_id.0, &other_tracks)?,
+                };
+                let new_id = match existing {
+                    Some(id) => id,
+                    None => self.create_album(album, None)?.0,
+                };
+                album_ids.insert(old_id.0, new_id);
+            }
+            let mut artist_name_counts: HashMap<String, usize> = HashMap::new();
+            for artist in &other_artists {
+                *artist_name_counts.entry(artist.name.clone()).or_default() += 1;
+            }
+            let mut artist_ids = HashMap::new();
+            for artist in other_artists {
+                let old_id = artist.artist_id.unwrap();
+                let existing = match linked_artist(c, old_id, &other_external_artists)? {
+                    Some(id) => Some(id),
+                    None if artist_name_counts[&artist.name] == 1 => artists::table
+                        .select(artists::artist_id)
+                        .filter(artists::name.eq(&artist.name))
+                        .log()
+                        .first::<Option<i64>>(c)
+                        .optional()?
+                        .flatten(),
+                    None => None,
+                };
+                let new_id = match existing {
+                    Some(id) => id,
+                    None => {
+                        insert_into(artists::table)
+                            .values(tables::Artist {
+                                artist_id: None,
+                                ..artist
+                            })
+                            .log()
+                            .execute(c)?;
+                        last_id(c)?
+                    }
+                };
+                artist_ids.insert(old_id, new_id);
+            }
+            let mut track_ids = HashMap::new();
+            let mut new_track_ids = HashSet::new();
+            for track in other_tracks {
+                let old_id = track.track_id.unwrap();
+                let existing: Option<i64> = match &track.file_path {
+                    Some(file_path) => tracks::table
+                        .select(tracks::track_id)
+                        .filter(tracks::file_path.eq(file_path))
+                        .log()
+                        .first::<Option<i64>>(c)
+                        .optional()?
+                        .flatten(),
+                    None => None,
+                };
+                let new_id = match existing {
+                    Some(id) => id,
+                    None => {
+                        insert_into(tracks::table)
+                            .values(tables::Track {
+                                track_id: None,
+                                album_id: imported_id(&album_ids, "album", track.album_id)?,
+                                artist_id: imported_id(&artist_ids, "artist", track.artist_id)?,
+                                ..track
+                            })
+                            .log()
+                            .execute(c)?;
+                        new_track_ids.insert(old_id);
+                        last_id(c)?
+                    }
+                };
+                track_ids.insert(old_id, new_id);
+            }
+            // tracks which were already here keep the artists they were credited to
+            for track_artist in other_track_artists {
+                if new_track_ids.contains(&track_artist.track_id) {
+                    insert_into(track_artists::table)
+                        .values(tables::TrackArtist {
+                            _id: None,
+                            track_id: imported_id(&track_ids, "track", track_artist.track_id)?,
+                            artist_id: imported_id(&artist_ids, "artist", track_artist.artist_id)?,
+                            ..track_artist
+                        })
+                        .log()
+                        .execute(c)?;
+                }
+            }
+            for external_album in other_external_albums {
+                let album_id = imported_id(&album_ids, "album", external_album.album_id)?;
+                let already_linked: bool = select(exists(
+                    external_albums::table
+                        .filter(external_albums::album_id.eq(album_id))
+                        .filter(external_albums::service_id.eq(&external_album.service_id))
+                        .filter(external_albums::external_id.eq(&external_album.external_id)),
+                ))
+                .log()
+                .get_result(c)?;
+                if !already_linked {
+                    insert_into(external_albums::table)
+                        .values(tables::ExternalAlbum {
+                            _id: None,
+                            album_id,
+                            ..external_album
+                        })
+                        .log()
+                        .execute(c)?;
+                }
+            }
+            for external_artist in other_external_artists {
+                let artist_id = imported_id(&artist_ids, "artist", external_artist.artist_id)?;
+                let already_linked: bool = select(exists(
+                    external_artists::table
+                        .filter(external_artists::artist_id.eq(artist_id))
+                        .filter(external_artists::service_id.eq(&external_artist.service_id))
+                        .filter(external_artists::external_id.eq(&external_artist.external_id)),
+                ))
+                .log()
+                .get_result(c)?;
+                if !already_linked {
+                    insert_into(external_artists::table)
+                        .values(tables::ExternalArtist {
+                            _id: None,
+                            artist_id,
+                            ..external_artist
+                        })
+                        .log()
+                        .execute(c)?;
+                }
+            }
+            for external_track in other_external_tracks {
+                let track_id = imported_id(&track_ids, "track", external_track.track_id)?;
+                let already_linked: bool = select(exists(
+                    external_tracks::table
+                        .filter(external_tracks::track_id.eq(track_id))
+                        .filter(external_tracks::service_id.eq(&external_track.service_id))
+                        .filter(external_tracks::external_id.eq(&external_track.external_id)),
+                ))
+                .log()
+                .get_result(c)?;
+                if !already_linked {
+                    insert_into(external_tracks::table)
+                        .values(tables::ExternalTrack {
+                            _id: None,
+                            track_id,
+                            ..external_track
+                        })
+                        .log()
+                        .execute(c)?;
+                }
+            }
+            let mut playlist_ids = HashMap::new();
+            for playlist in other_playlists {
+                let name_taken: bool = select(exists(
+                    playlists::table.filter(playlists::name.eq(&playlist.name)),
+                ))
+                .log()
+                .get_result(c)?;
+                if name_taken {
+                    log::warn!(
+                        "not importing playlist {}, name already taken",
+                        playlist.name
+                    );
+                    continue;
+                }
+                let old_id = playlist.playlist_id.unwrap();
+                insert_into(playlists::table)
+                    .values(tables::Playlist {
+                        playlist_id: None,
+                        ..playlist
+                    })
+                    .log()
+                    .execute(c)?;
+                playlist_ids.insert(old_id, last_id(c)?);
+            }
+            for playlist_track in other_playlist_tracks {
+                if let Some(&playlist_id) = playlist_ids.get(&playlist_track.playlist_id) {
+                    insert_into(playlist_tracks::table)
+                        .values(tables::PlaylistTrack {
+                            _id: None,
+                            playlist_id,
+                            track_id: imported_id(&track_ids, "track", playlist_track.track_id)?,
+                            position: playlist_track.position,
+                        })
+                        .log()
+                        .execute(c)?;
+                }
+            }
+            log::info!(
+                "imported {} tracks and {} playlists",
+                track_ids.len(),
+                playlist_ids.len()
+            );
+            Ok(())
+        })
+    }
+
+    /// Searches track titles, album titles and artist names for every word of the query, or words
+    /// starting with them so that results show up while the last word is still being typed.
+    /// Tracks are also found by any artist credited on them and their album. The best matches come
+    /// first.
+    pub fn search(&self, query: &str) -> Try<SearchResults> {
+        let query = match search_index_query(query) {
+            Some(query) => query,
+            None => return Ok(SearchResults::default()),
+        };
+        // matches in titles and names count for more than matches in other columns
+        let track_ids = self.search_index("tracks_search", "10.0, 5.0, 2.0", &query)?;
+        let album_ids = self.search_index("albums_search", "10.0, 5.0", &query)?;
+        let artist_ids = self.search_index("artists_search", "10.0", &query)?;
+
+        let track_rows: Vec<(tables::Track, tables::Album, tables::Artist)> = tracks::table
+            .filter(tracks::track_id.eq_any(&track_ids))
+            .filter(tracks::missing.eq(false))
+            .inner_join(albums::table)
+            .inner_join(artists::table)
+            .log()
+            .load(self.connection()?)?;
+        let track_rows = track_rows
+            .into_iter()
+            .map(|row| (row.0.track_id.unwrap(), row));
+        let album_rows: Vec<tables::Album> = albums::table
+            .filter(albums::album_id.eq_any(&album_ids))
+            .log()
+            .load(self.connection()?)?;
+        let album_rows = album_rows
+            .into_iter()
+            .map(|row| (row.album_id.unwrap(), row));
+        let artist_rows: Vec<tables::Artist> = artists::table
+            .filter(artists::artist_id.eq_any(&artist_ids))
+            .log()
+            .load(self.connection()?)?;
+        let artist_rows = artist_rows
+            .into_iter()
+            .map(|row| (row.artist_id.unwrap(), row));
+
+        Ok(SearchResults {
+            tracks: in_order(&track_ids, track_rows)
+                .into_iter()
+                .map(|row| {
+                    let track = into_track(row, vec![], vec![]);
+                    TrackSearchResult {
+                        track: library_result(track.track_id, track.track_info),
+                        artist: library_result(track.artist_id, track.artist_info),
+                        album: library_result(track.album_id, track.album_info),
+                    }
+                })
+                .collect(),
+            albums: in_order(&album_ids, album_rows)
+                .into_iter()
+                .map(into_album)
+                .map(|(id, info)| library_result(id, info))
+                .collect(),
+            artists: in_order(&artist_ids, artist_rows)
+                .into_iter()
+                .map(into_artist)
+                .map(|(id, info)| library_result(id, info))
+                .collect(),
+            ..SearchResults::default()
+        })
+    }
+
+    /// Returns the IDs of the best matches for a query in one of the full text search indexes,
+    /// weighting matches in each column of the index.
+    fn search_index(&self, index: &str, weights: &str, query: &str) -> Try<Vec<i64>> {
+        #[derive(QueryableByName)]
+        struct Match {
+            #[sql_type = "sql_types::BigInt"]
+            rowid: i64,
+        }
+        let matches: Vec<Match> = sql_query(format!(
+            "SELECT rowid FROM {index} WHERE {index} MATCH ? ORDER BY bm25({index}, {weights}) LIMIT ?",
+            index = index,
+            weights = weights
+        ))
+        .bind::<sql_types::Text, _>(query)
+        .bind::<sql_types::BigInt, _>(SEARCH_LIMIT)
+        .log()
+        .load(self.connection()?)?;
+        Ok(matches.into_iter().map(|m| m.rowid).collect())
+    }
+
+    /// Fills in the library IDs of search results from services which are already in the library.
     pub fn resolve(&self, mut search_results: SearchResults) -> Try<SearchResults> {
-        // TODO: search
+        let tracks = &search_results.tracks;
+        let track_ids = external_ids(tracks.iter().map(|t| &t.track));
+        let album_ids = external_ids(
+            tracks
+                .iter()
+                .map(|t| &t.album)
+                .chain(&search_results.albums),
+        );
+        let artist_ids = external_ids(
+            tracks
+                .iter()
+                .map(|t| &t.artist)
+                .chain(&search_results.artists),
+        );
+
+        let track_ids: Vec<(String, String, i64)> = external_tracks::table
+            .select((
+                external_tracks::service_id,
+                external_tracks::external_id,
+                external_tracks::track_id,
+            ))
+            .filter(external_tracks::external_id.eq_any(track_ids))
+            .log()
+            .load(self.connection()?)?;
+        let album_ids: Vec<(String, String, i64)> = external_albums::table
+            .select((
+                external_albums::service_id,
+                external_albums::external_id,
+                external_albums::album_id,
+            ))
+            .filter(external_albums::external_id.eq_any(album_ids))
+            .log()
+            .load(self.connection()?)?;
+        let artist_ids: Vec<(String, String, i64)> = external_artists::table
+            .select((
+                external_artists::service_id,
+                external_artists::external_id,
+                external_artists::artist_id,
+            ))
+            .filter(external_artists::external_id.eq_any(artist_ids))
+            .log()
+            .load(self.connection()?)?;
+        let track_ids = by_external_id(track_ids);
+        let album_ids = by_external_id(album_ids);
+        let artist_ids = by_external_id(artist_ids);
+
+        for track in &mut search_results.tracks {
+            link_to_library(&mut track.track, &track_ids);
+            link_to_library(&mut track.album, &album_ids);
+            link_to_library(&mut track.artist, &artist_ids);
+        }
+        for album in &mut search_results.albums {
+            link_to_library(album, &album_ids);
+        }
+        for artist in &mut search_results.artists {
+            link_to_library(artist, &artist_ids);
+        }
         Ok(search_results)
     }
 
-    pub fn new(file_path: String, event_sink: Arc<EventSink>) -> Try<Library> {
+    pub fn new(
+        file_path: String,
+        event_sink: Arc<EventSink>,
+        art_cache: Option<Arc<ArtCache>>,
+    ) -> Try<Library> {
         let db = Library {
             connection: CachedThreadLocal::new(),
             file_path,
             event_sink,
+            art_cache,
         };
         db.setup()?;
         Ok(db)
@@ -334,30 +1006,384 @@ impl Library {
     }
 
     pub fn add_local_track(&self, file_path: String) -> Try<LibraryId<Track>> {
-        let (track, album, artist) = if file_path.ends_with(".mp3") {
-            file_formats::mp3::read_metadata(file_path)?
-        } else if file_path.ends_with(".flac") {
-            file_formats::flac::read_metadata(file_path)?
-        } else {
-            return Err(anyhow!("unsupported file type {}", file_path));
+        self.add_local_track_metadata(file_formats::read_metadata(file_path)?)
+    }
+
+    /// Adds a track to the library given metadata already read from its file.
+    pub fn add_local_track_metadata(
+        &self,
+        (track, album, artists): Metadata,
+    ) -> Try<LibraryId<Track>> {
+        let album_id = self.find_or_create_local_album(album)?;
+        self.add_local_cover_art(album_id, &track)?;
+        let artists = self.find_or_create_local_artists(artists)?;
+        Ok(self.create_track(track, album_id, &artists, None)?)
+    }
+
+    /// Gives an album the cover art from one of its track's files, unless it already has some.
+    fn add_local_cover_art(&self, album_id: LibraryId<Album>, track: &TrackInfo) -> Try<()> {
+        let (art_cache, file_path) = match (&self.art_cache, &track.file_path) {
+            (Some(art_cache), Some(file_path)) => (art_cache, file_path),
+            _ => return Ok(()),
+        };
+        let has_cover: bool = select(exists(
+            albums::table
+                .find(album_id.0)
+                .filter(albums::cover_image_url.is_not_null()),
+        ))
+        .log()
+        .get_result(self.connection()?)?;
+        if has_cover {
+            return Ok(());
+        }
+        let url = match file_formats::read_cover_art(file_path)
+            .and_then(|picture| picture.map(|p| art_cache.store(&p)).transpose())
+        {
+            Ok(Some(url)) => url,
+            Ok(None) => return Ok(()),
+            // the track is still worth having without its cover
+            Err(e) => {
+                log::warn!("failed to read cover art from {}: {:#}", file_path, e);
+                return Ok(());
+            }
         };
-        let album_id = self
-            .find_albums_by_name(&album.title)
-            .map(|a| a.first().map(|(id, _)| *id))
-            .transpose()
-            .unwrap_or_else(|| self.create_album(album, None))?;
-        let artist_id = self
-            .find_artists_by_name(&artist.name)
-            .map(|a| a.first().map(|(id, _)| *id))
-            .transpose()
-            .unwrap_or_else(|| self.create_artist(artist, None))?;
-        Ok(self.create_track(track, album_id, artist_id, None)?)
+        update(albums::table.find(album_id.0))
+            .set(albums::cover_image_url.eq(url))
+            .log()
+            .execute(self.connection()?)?;
+        Ok(())
     }
+
+    fn find_or_create_local_album(&self, album: AlbumInfo) -> Try<LibraryId<Album>> {
+        let album_id = match self.find_local_album(&album)? {
+            Some(album_id) => album_id,
+            None => return self.create_album(album, None),
+        };
+        if let Some(musicbrainz_id) = album.musicbrainz_id {
+            // albums added before their files were tagged pick up the ID from newer tracks
+            update(
+                albums::table
+                    .find(album_id.0)
+                    .filter(albums::musicbrainz_id.is_null()),
+            )
+            .set(albums::musicbrainz_id.eq(musicbrainz_id))
+            .log()
+            .execute(self.connection()?)?;
+        }
+        Ok(album_id)
+    }
+
+    /// Splits up albums which hold tracks from different albums, as happened when local albums
+    /// were told apart by title alone. Local tracks are regrouped by the album their file's tags
+    /// say they're on, and the biggest group keeps the album. Albums with a file that can't be
+    /// read are left alone, since we can't tell which album its track is on. Returns how many
+    /// albums were split up.
+    pub fn split_merged_albums(&self) -> Try<usize> {
+        let albums: Vec<tables::Album> = albums::table.load(self.connection()?)?;
+        let mut split = 0;
+        'albums: for album in albums {
+            let (album_id, album_info) = into_album(album);
+            let tracks: Vec<tables::Track> = tracks::table
+                .filter(tracks::album_id.eq(album_id.0))
+                .filter(tracks::file_path.is_not_null())
+                .log()
+                .load(self.connection()?)?;
+            let mut groups: Vec<(AlbumInfo, Vec<i64>)> = Vec::new();
+            for track in tracks {
+                let file_path = track.file_path.unwrap();
+                let track_album = match file_formats::read_metadata(file_path.clone()) {
+                    Ok((_, track_album, _)) => track_album,
+                    Err(e) => {
+                        log::warn!(
+                            "not splitting album {}, can't read tags of {}: {}",
+                            album_info.title,
+                            file_path,
+                            e
+                        );
+                        continue 'albums;
+                    }
+                };
+                let track_id = track.track_id.unwrap();
+                match groups
+                    .iter_mut()
+                    .find(|(group, _)| same_album(group, &track_album))
+                {
+                    Some((_, track_ids)) => track_ids.push(track_id),
+                    None => groups.push((track_album, vec![track_id])),
+                }
+            }
+            if groups.len() < 2 {
+                continue;
+            }
+            groups.sort_by_key(|(_, track_ids)| Reverse(track_ids.len()));
+            log::info!(
+                "splitting album {} into {} albums",
+                album_info.title,
+                groups.len()
+            );
+            split += 1;
+            self.in_transaction(|c| {
+                let mut groups = groups.into_iter();
+                let (kept, _) = groups.next().unwrap();
+                update(albums::table.find(album_id.0))
+                    .set((
+                        albums::album_artist.eq(kept.album_artist),
+                        albums::musicbrainz_id.eq(kept.musicbrainz_id),
+                    ))
+                    .log()
+                    .execute(c)?;
+                for (group, track_ids) in groups {
+                    let new_album_id = self.find_or_create_local_album(group)?;
+                    update(tracks::table.filter(tracks::track_id.eq_any(&track_ids)))
+                        .set(tracks::album_id.eq(new_album_id.0))
+                        .log()
+                        .execute(c)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(split)
+    }
+
+    fn find_or_create_local_artists(
+        &self,
+        artists: Vec<(ArtistInfo, ArtistRole)>,
+    ) -> Try<Vec<(LibraryId<Artist>, ArtistRole)>> {
+        artists
+            .into_iter()
+            .map(|(artist, role)| {
+                let artist_id = self
+                    .find_artists_by_name(&artist.name)
+                    .map(|a| a.first().map(|(id, _)| *id))
+                    .transpose()
+                    .unwrap_or_else(|| self.create_artist(artist, None))?;
+                Ok((artist_id, role))
+            })
+            .collect()
+    }
+
+    /// Returns the tracks whose files are at the given path, or anywhere under it if it is a
+    /// directory.
+    pub fn find_local_tracks_under(&self, path: &str) -> Try<Vec<(LibraryId<Track>, String)>> {
+        let directory_prefix = format!(
+            "{}{}",
+            path.trim_end_matches(MAIN_SEPARATOR),
+            MAIN_SEPARATOR
+        );
+        let rows: Vec<(Option<i64>, Option<String>)> = tracks::table
+            .select((tracks::track_id, tracks::file_path))
+            .filter(
+                tracks::file_path
+                    .eq(path)
+                    // LIKE treats _ as a wildcard, so we check the prefix properly below
+                    .or(tracks::file_path.like(format!("{}%", directory_prefix))),
+            )
+            .log()
+            .load(self.connection()?)?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|(id, file_path)| Some((LibraryId::new(id?), file_path?)))
+            .filter(|(_, file_path)| file_path == path || file_path.starts_with(&directory_prefix))
+            .collect())
+    }
+
+    /// Updates a track with metadata read again from its file.
+    pub fn update_local_track(
+        &self,
+        track_id: LibraryId<Track>,
+        (track, album, artists): Metadata,
+    ) -> Try<()> {
+        let album_id = self.find_or_create_local_album(album)?;
+        self.add_local_cover_art(album_id, &track)?;
+        let artists = self.find_or_create_local_artists(artists)?;
+        let artist_id = primary_artist(&artists)?;
+        self.in_transaction(|c| {
+            update(tracks::table.find(track_id.0))
+                .set((
+                    tracks::album_id.eq(album_id.0),
+                    tracks::artist_id.eq(artist_id.0),
+                    tracks::title.eq(track.title),
+                    tracks::isrc.eq(track.isrc),
+                    tracks::duration_secs.eq(track.duration_secs),
+                    tracks::file_path.eq(track.file_path),
+                    tracks::missing.eq(false),
+                    tracks::track_number.eq(track.track_number.map(|n| n as i32)),
+                    tracks::disc_number.eq(track.disc_number.map(|n| n as i32)),
+                    tracks::genre.eq(track.genre),
+                    tracks::composer.eq(track.composer),
+                ))
+                .log()
+                .execute(c)?;
+            delete(track_artists::table.filter(track_artists::track_id.eq(track_id.0)))
+                .log()
+                .execute(c)?;
+            insert_credits(c, track_id.0, &artists)
+        })?;
+        self.broadcast_track_updated(track_id)
+    }
+
+    /// Records that a track's file has moved.
+    pub fn move_local_track(&self, track_id: LibraryId<Track>, file_path: String) -> Try<()> {
+        update(tracks::table.find(track_id.0))
+            .set((tracks::file_path.eq(file_path), tracks::missing.eq(false)))
+            .log()
+            .execute(self.connection()?)?;
+        self.broadcast_track_updated(track_id)
+    }
+
+    /// Records that a track's file has disappeared, or come back. Nothing is broadcast if the track
+    /// was already in that state.
+    pub fn set_track_missing(&self, track_id: LibraryId<Track>, missing: bool) -> Try<()> {
+        let updated = update(
+            tracks::table
+                .find(track_id.0)
+                .filter(tracks::missing.ne(missing)),
+        )
+        .set(tracks::missing.eq(missing))
+        .log()
+        .execute(self.connection()?)?;
+        if updated > 0 {
+            self.broadcast_track_updated(track_id)?;
+        }
+        Ok(())
+    }
+
+    fn broadcast_track_updated(&self, track_id: LibraryId<Track>) -> Try<()> {
+        let track = self
+            .get_track(track_id)?
+            .ok_or_else(|| anyhow!("Unknown track {}", track_id))?;
+        self.event_sink.broadcast(&Event::TrackUpdated(track));
+        Ok(())
+    }
+}
+
+/// Turns what someone typed into a full text search query, which matches rows containing every word
+/// or a word starting with it, or returns `None` if there's nothing to search for.
+fn search_index_query(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        // the tokenizer ignores punctuation, so a word of only punctuation would match nothing
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        // quoting stops words being read as query syntax, such as AND or NOT
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+/// Picks out the rows with the given IDs, in the order of the IDs.
+fn in_order<T>(ids: &[i64], rows: impl Iterator<Item = (i64, T)>) -> Vec<T> {
+    let mut rows: HashMap<i64, T> = rows.collect();
+    ids.iter().filter_map(|id| rows.remove(id)).collect()
+}
+
+/// A playlist left joined with one of its entries and the entry's track.
+type PlaylistRow = (
+    tables::Playlist,
+    Option<(tables::PlaylistTrack, tables::Track)>,
+);
+
+/// Groups the rows of playlists joined with their entries into playlists, keeping the rows' order.
+fn into_playlists(rows: Vec<PlaylistRow>) -> Vec<Playlist> {
+    let mut playlists: Vec<Playlist> = Vec::new();
+    for (playlist, entry) in rows {
+        let id = LibraryId::new(playlist.playlist_id.unwrap());
+        if playlists.last().map(|p| p.id) != Some(id) {
+            playlists.push(Playlist {
+                id,
+                name: playlist.name,
+                track_count: 0,
+                duration_secs: 0.0,
+                track_ids: Vec::new(),
+            });
+        }
+        if let Some((_, track)) = entry {
+            let playlist = playlists.last_mut().unwrap();
+            playlist
+                .track_ids
+                .push(LibraryId::new(track.track_id.unwrap()));
+            playlist.track_count += 1;
+            playlist.duration_secs += track.duration_secs;
+        }
+    }
+    playlists
+}
+
+fn library_result<E: Entity, T>(id: LibraryId<E>, info: T) -> SearchResult<E, T> {
+    SearchResult {
+        library_id: Some(id),
+        external_ids: vec![],
+        info,
+    }
+}
+
+/// Returns the distinct service specific IDs of search results, so they can be looked up at once.
+fn external_ids<'a, E: Entity + 'a, T: 'a>(
+    results: impl Iterator<Item = &'a SearchResult<E, T>>,
+) -> Vec<String> {
+    let ids: HashSet<&str> = results
+        .flat_map(|r| &r.external_ids)
+        .map(|id| id.id.0.as_str())
+        .collect();
+    ids.into_iter().map(str::to_string).collect()
+}
+
+/// Indexes rows of an external IDs table by service and service specific ID.
+fn by_external_id(rows: Vec<(String, String, i64)>) -> HashMap<(String, String), i64> {
+    rows.into_iter()
+        .map(|(service_id, external_id, id)| ((service_id, external_id), id))
+        .collect()
+}
+
+fn link_to_library<E: Entity, T>(
+    result: &mut SearchResult<E, T>,
+    library_ids: &HashMap<(String, String), i64>,
+) {
+    for external_id in &result.external_ids {
+        let key = (external_id.service.0.clone(), external_id.id.0.clone());
+        if let Some(&id) = library_ids.get(&key) {
+            result.library_id = Some(LibraryId::new(id));
+            return;
+        }
+    }
+}
+
+/// Returns the artist a track is listed under, which is the first primary artist credited.
+fn primary_artist(artists: &[(LibraryId<Artist>, ArtistRole)]) -> Try<LibraryId<Artist>> {
+    artists
+        .iter()
+        .find(|(_, role)| *role == ArtistRole::Primary)
+        .map(|(id, _)| *id)
+        .ok_or_else(|| anyhow!("no primary artist credited"))
+}
+
+fn insert_credits(
+    c: &SqliteConnection,
+    track_id: i64,
+    artists: &[(LibraryId<Artist>, ArtistRole)],
+) -> Try<()> {
+    for (position, (artist_id, role)) in artists.iter().enumerate() {
+        insert_into(track_artists::table)
+            .values(tables::TrackArtist {
+                _id: None,
+                track_id,
+                artist_id: artist_id.0,
+                role: role.to_string(),
+                position: position as i32,
+            })
+            .log()
+            .execute(c)?;
+    }
+    Ok(())
 }
 
 fn into_track(
     (track, album, artist): (tables::Track, tables::Album, tables::Artist),
     external_ids: Vec<tables::ExternalTrack>,
+    artists: Vec<ArtistCredit>,
 ) -> TrackSummary {
     TrackSummary {
         track_id: LibraryId::new(track.track_id.unwrap()),
@@ -373,18 +1399,39 @@ fn into_track(
             isrc: track.isrc,
             duration_secs: track.duration_secs,
             file_path: track.file_path,
+            track_number: track.track_number.map(|n| n as u32),
+            disc_number: track.disc_number.map(|n| n as u32),
+            genre: track.genre,
+            composer: track.composer,
         },
         artist_id: LibraryId::new(track.artist_id),
         artist_info: ArtistInfo {
             name: artist.name,
             image_url: artist.image_url.map(|u| u.parse().unwrap()),
         },
+        artists,
         album_id: LibraryId::new(track.album_id),
         album_info: AlbumInfo {
             title: album.title,
-            cover_image_url: album.cover_image_url.map(|u| u.parse().unwrap()),
+            cover_image_url: album.cover_image_url,
             release_date: album.release_date.map(|d| d.parse().unwrap()),
+            album_artist: album.album_artist,
+            year: album.year,
+            musicbrainz_id: album.musicbrainz_id,
         },
+        missing: track.missing,
+    }
+}
+
+/// Whether two local albums with the same title are the same album. Albums of different MusicBrainz
+/// releases are different even if they're by the same artist, such as two editions of an album.
+fn same_album(a: &AlbumInfo, b: &AlbumInfo) -> bool {
+    match (&a.musicbrainz_id, &b.musicbrainz_id) {
+        (Some(a_id), Some(b_id)) if a_id != b_id => false,
+        _ => {
+            let artist = |album: &AlbumInfo| album.album_artist.as_ref().map(|a| a.to_lowercase());
+            artist(a) == artist(b)
+        }
     }
 }
 
@@ -393,8 +1440,11 @@ fn into_album(a: tables::Album) -> (LibraryId<Album>, AlbumInfo) {
         LibraryId::new(a.album_id.unwrap()),
         AlbumInfo {
             title: a.title,
-            cover_image_url: a.cover_image_url.map(|u| u.parse().unwrap()),
+            cover_image_url: a.cover_image_url,
             release_date: a.release_date.map(|d| d.parse().unwrap()),
+            album_artist: a.album_artist,
+            year: a.year,
+            musicbrainz_id: a.musicbrainz_id,
         },
     )
 }
@@ -424,7 +1474,676 @@ embed_migrations!();
 
 no_arg_sql_function!(last_insert_rowid, sql_types::BigInt);
 
+/// Returns the album in this library which is linked to any of the external albums an album in
+/// another library is linked to.
+fn linked_album(
+    c: &SqliteConnection,
+    old_album_id: i64,
+    other_external_albums: &[tables::ExternalAlbum],
+) -> Try<Option<i64>> {
+    for external_album in other_external_albums {
+        if external_album.album_id != old_album_id {
+            continue;
+        }
+        let album_id = external_albums::table
+            .select(external_albums::album_id)
+            .filter(external_albums::service_id.eq(&external_album.service_id))
+            .filter(external_albums::external_id.eq(&external_album.external_id))
+            .log()
+            .first::<i64>(c)
+            .optional()?;
+        if album_id.is_some() {
+            return Ok(album_id);
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the artist in this library which is linked to any of the external artists an artist in
+/// another library is linked to.
+fn linked_artist(
+    c: &SqliteConnection,
+    old_artist_id: i64,
+    other_external_artists: &[tables::ExternalArtist],
+) -> Try<Option<i64>> {
+    for external_artist in other_external_artists {
+        if external_artist.artist_id != old_artist_id {
+            continue;
+        }
+        let artist_id = external_artists::table
+            .select(external_artists::artist_id)
+            .filter(external_artists::service_id.eq(&external_artist.service_id))
+            .filter(external_artists::external_id.eq(&external_artist.external_id))
+            .log()
+            .first::<i64>(c)
+            .optional()?;
+        if artist_id.is_some() {
+            return Ok(artist_id);
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the album in this library holding any of the files on an album in another library.
+fn album_holding_files(
+    c: &SqliteConnection,
+    old_album_id: i64,
+    other_tracks: &[tables::Track],
+) -> Try<Option<i64>> {
+    let file_paths: Vec<String> = other_tracks
+        .iter()
+        .filter(|track| track.album_id == old_album_id)
+        .filter_map(|track| track.file_path.clone())
+        .collect();
+    Ok(tracks::table
+        .select(tracks::album_id)
+        .filter(tracks::file_path.eq_any(file_paths))
+        .log()
+        .first::<i64>(c)
+        .optional()?)
+}
+
+/// Returns the ID an imported row was given in this library, given its ID in the library it was
+/// imported from. Foreign keys aren't enforced, so the row it refers to may not exist there.
+fn imported_id(ids: &HashMap<i64, i64>, kind: &str, old_id: i64) -> Try<i64> {
+    ids.get(&old_id)
+        .copied()
+        .ok_or_else(|| anyhow!("imported library refers to missing {} {}", kind, old_id))
+}
+
 /// Returns the rowid of the last row inserted by this database connection.
 fn last_id(con: &SqliteConnection) -> QueryResult<i64> {
     select(last_insert_rowid).first(con)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::ops::Deref;
+    use tempfile::TempDir;
+
+    /// A library in a temporary directory of its own, which is deleted along with it.
+    struct TestLibrary {
+        library: Library,
+        _directory: TempDir,
+    }
+
+    impl Deref for TestLibrary {
+        type Target = Library;
+
+        fn deref(&self) -> &Library {
+            &self.library
+        }
+    }
+
+    /// Opens a new, empty library in a temporary file.
+    fn library() -> TestLibrary {
+        let directory = tempfile::tempdir().unwrap();
+        let file_path = directory.path().join("library.sqlite");
+        let file_path = file_path.to_str().unwrap().to_string();
+        TestLibrary {
+            library: Library::new(file_path, Arc::new(EventSink::empty()), None).unwrap(),
+            _directory: directory,
+        }
+    }
+
+    fn add_track(library: &Library, title: &str, album: &str, artist: &str) -> LibraryId<Track> {
+        add_album_track(library, title, album_info(album, artist, None), artist)
+    }
+
+    fn album_info(title: &str, album_artist: &str, musicbrainz_id: Option<&str>) -> AlbumInfo {
+        AlbumInfo {
+            title: title.to_string(),
+            cover_image_url: None,
+            release_date: None,
+            album_artist: Some(album_artist.to_string()),
+            year: None,
+            musicbrainz_id: musicbrainz_id.map(|id| id.to_string()),
+        }
+    }
+
+    fn add_album_track(
+        library: &Library,
+        title: &str,
+        album: AlbumInfo,
+        artist: &str,
+    ) -> LibraryId<Track> {
+        let track = TrackInfo {
+            title: title.to_string(),
+            isrc: None,
+            duration_secs: 1.0,
+            file_path: Some(format!("/music/{}.flac", title)),
+            track_number: None,
+            disc_number: None,
+            genre: None,
+            composer: None,
+        };
+        let artist = ArtistInfo {
+            name: artist.to_string(),
+            image_url: None,
+        };
+        library
+            .add_local_track_metadata((track, album, vec![(artist, ArtistRole::Primary)]))
+            .unwrap()
+    }
+
+    fn track_ids(results: &SearchResults) -> Vec<i64> {
+        results
+            .tracks
+            .iter()
+            .map(|t| t.track.library_id.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn search_matches_prefixes_ignoring_accents() {
+        let library = library();
+        let halo = add_track(&library, "Halo", "I Am... Sasha Fierce", "Beyoncé");
+        add_track(&library, "Hello", "25", "Adele");
+
+        let results = library.search("beyon").unwrap();
+        assert_eq!(results.artists.len(), 1);
+        assert_eq!(results.artists[0].info.name, "Beyoncé");
+        assert_eq!(results.albums.len(), 1);
+        assert_eq!(track_ids(&results), vec![halo.0]);
+
+        // every word has to match, but they can be in different columns
+        assert_eq!(
+            track_ids(&library.search("sasha ha").unwrap()),
+            vec![halo.0]
+        );
+        assert!(library.search("sasha hello").unwrap().tracks.is_empty());
+    }
+
+    #[test]
+    fn search_finds_tracks_by_every_credited_artist() {
+        let library = library();
+        let track = TrackInfo {
+            title: "Song".to_string(),
+            isrc: None,
+            duration_secs: 1.0,
+            file_path: Some("/music/Song.flac".to_string()),
+            track_number: None,
+            disc_number: None,
+            genre: None,
+            composer: None,
+        };
+        let artist = |name: &str| ArtistInfo {
+            name: name.to_string(),
+            image_url: None,
+        };
+        let track_id = library
+            .add_local_track_metadata((
+                track,
+                album_info("Album", "Singer", None),
+                vec![
+                    (artist("Singer"), ArtistRole::Primary),
+                    (artist("Rapper"), ArtistRole::Featured),
+                    (artist("Producer"), ArtistRole::Remixer),
+                ],
+            ))
+            .unwrap();
+        assert_eq!(
+            track_ids(&library.search("rapper").unwrap()),
+            vec![track_id.0]
+        );
+        assert_eq!(
+            track_ids(&library.search("singer producer").unwrap()),
+            vec![track_id.0]
+        );
+    }
+
+    #[test]
+    fn search_queries_are_not_query_syntax() {
+        let library = library();
+        add_track(&library, "Song", "Album", "Artist");
+        assert!(library.search("\"").unwrap().tracks.is_empty());
+        assert!(library.search("song NOT").unwrap().tracks.is_empty());
+        assert_eq!(
+            search_index_query("a \"b"),
+            Some("\"a\"* \"\"\"b\"*".to_string())
+        );
+        assert_eq!(search_index_query(" - "), None);
+    }
+
+    fn playlist_titles(library: &Library, id: LibraryId<crate::ids::Playlist>) -> Vec<String> {
+        let playlist = library.get_playlist(id).unwrap().unwrap();
+        playlist
+            .tracks()
+            .map(|track_id| library.get_track(track_id).unwrap().unwrap())
+            .map(|track| track.track_info.title)
+            .collect()
+    }
+
+    #[test]
+    fn playlist_entries_keep_their_positions() {
+        let library = library();
+        let a = add_track(&library, "A", "Album", "Artist");
+        let b = add_track(&library, "B", "Album", "Artist");
+        let c = add_track(&library, "C", "Album", "Artist");
+        let playlist = library.create_playlist("Playlist".to_string()).unwrap();
+        library.add_track_to_playlist(a, playlist, None).unwrap();
+        library.add_track_to_playlist(b, playlist, None).unwrap();
+        library.add_track_to_playlist(c, playlist, Some(0)).unwrap();
+        library.add_track_to_playlist(a, playlist, Some(2)).unwrap();
+        assert_eq!(
+            playlist_titles(&library, playlist),
+            vec!["C", "A", "A", "B"]
+        );
+        assert!(library.add_track_to_playlist(a, playlist, Some(5)).is_err());
+
+        library.move_playlist_track(playlist, 0, 3).unwrap();
+        assert_eq!(
+            playlist_titles(&library, playlist),
+            vec!["A", "A", "B", "C"]
+        );
+        library.move_playlist_track(playlist, 2, 0).unwrap();
+        assert_eq!(
+            playlist_titles(&library, playlist),
+            vec!["B", "A", "A", "C"]
+        );
+        library.remove_from_playlist(playlist, 1).unwrap();
+        assert_eq!(playlist_titles(&library, playlist), vec!["B", "A", "C"]);
+        assert!(library.remove_from_playlist(playlist, 3).is_err());
+    }
+
+    #[test]
+    fn artist_tracks_are_in_album_order() {
+        let library = library();
+        let later = add_track(&library, "Later", "B Album", "Artist");
+        add_track(&library, "Someone Else's", "B Album", "Other");
+        let earlier = add_track(&library, "Earlier", "A Album", "Artist");
+        let artist_id = library
+            .find_or_create_local_artists(vec![(
+                ArtistInfo {
+                    name: "Artist".to_string(),
+                    image_url: None,
+                },
+                ArtistRole::Primary,
+            )])
+            .unwrap()[0]
+            .0;
+        let track_ids: Vec<i64> = library
+            .artist_track_ids(artist_id)
+            .unwrap()
+            .iter()
+            .map(|id| id.0)
+            .collect();
+        assert_eq!(track_ids, vec![earlier.0, later.0]);
+    }
+
+    fn album_id(library: &Library, track_id: LibraryId<Track>) -> i64 {
+        library.get_track(track_id).unwrap().unwrap().album_id.0
+    }
+
+    #[test]
+    fn albums_with_the_same_title_by_different_artists_are_different() {
+        let library = library();
+        let queen = add_track(&library, "Bohemian Rhapsody", "Greatest Hits", "Queen");
+        let abba = add_track(&library, "Waterloo", "Greatest Hits", "ABBA");
+        let queen_again = add_track(&library, "Don't Stop Me Now", "Greatest Hits", "queen");
+        assert_ne!(album_id(&library, queen), album_id(&library, abba));
+        assert_eq!(album_id(&library, queen), album_id(&library, queen_again));
+    }
+
+    #[test]
+    fn musicbrainz_ids_win_over_titles_and_artists() {
+        let library = library();
+        let original = add_album_track(
+            &library,
+            "Song",
+            album_info("Album", "Artist", Some("release-1")),
+            "Artist",
+        );
+        // the same release, even though the album artist is tagged differently
+        let same_release = add_album_track(
+            &library,
+            "Other Song",
+            album_info("Album (Deluxe)", "Various Artists", Some("release-1")),
+            "Someone Else",
+        );
+        // another edition of the album, with the same title and artist
+        let other_release = add_album_track(
+            &library,
+            "Bonus Song",
+            album_info("Album", "Artist", Some("release-2")),
+            "Artist",
+        );
+        assert_eq!(
+            album_id(&library, original),
+            album_id(&library, same_release)
+        );
+        assert_ne!(
+            album_id(&library, original),
+            album_id(&library, other_release)
+        );
+    }
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/file_formats/fixtures/tagged.m4a"
+    );
+
+    /// Moves a track to another album, as older versions did for albums with the same title, and
+    /// deletes the album it was on.
+    fn merge_into(library: &Library, track_id: LibraryId<Track>, merged_album_id: i64) {
+        let old_album_id = album_id(library, track_id);
+        let c = library.connection().unwrap();
+        update(tracks::table.find(track_id.0))
+            .set(tracks::album_id.eq(merged_album_id))
+            .execute(c)
+            .unwrap();
+        delete(albums::table.find(old_album_id)).execute(c).unwrap();
+    }
+
+    #[test]
+    fn merged_albums_are_split_once() {
+        let library = library();
+        let directory = tempfile::tempdir().unwrap();
+        let fixture = fs::read(FIXTURE).unwrap();
+        // copies of the fixture, tagged with an artist of the same length
+        let add_file = |name: &str, artist: &[u8]| {
+            let mut data = fixture.clone();
+            let offset = data.windows(11).position(|w| w == b"Artist Name").unwrap();
+            data[offset..offset + 11].copy_from_slice(artist);
+            let file_path = directory.path().join(name);
+            fs::write(&file_path, data).unwrap();
+            library
+                .add_local_track(file_path.to_str().unwrap().to_string())
+                .unwrap()
+        };
+        let first = add_file("first.m4a", b"Artist Name");
+        let second = add_file("second.m4a", b"Artist Name");
+        let other = add_file("other.m4a", b"Other Group");
+        let merged_album_id = album_id(&library, first);
+        merge_into(&library, other, merged_album_id);
+
+        assert_eq!(library.split_merged_albums().unwrap(), 1);
+        assert_eq!(album_id(&library, first), merged_album_id);
+        assert_eq!(album_id(&library, second), merged_album_id);
+        let split_album_id = album_id(&library, other);
+        assert_ne!(split_album_id, merged_album_id);
+        let split_album = library.get_track(other).unwrap().unwrap().album_info;
+        assert_eq!(split_album.title, "Album Title");
+        assert_eq!(split_album.album_artist, Some("Other Group".to_string()));
+
+        assert_eq!(library.split_merged_albums().unwrap(), 0);
+        assert_eq!(album_id(&library, first), merged_album_id);
+        assert_eq!(album_id(&library, other), split_album_id);
+    }
+
+    #[test]
+    fn albums_with_unreadable_files_are_not_split() {
+        let library = library();
+        let queen = add_track(&library, "Bohemian Rhapsody", "Greatest Hits", "Queen");
+        let abba = add_track(&library, "Waterloo", "Greatest Hits", "ABBA");
+        let merged_album_id = album_id(&library, queen);
+        merge_into(&library, abba, merged_album_id);
+
+        // the files don't exist, so there's no telling which album the tracks are on
+        assert_eq!(library.split_merged_albums().unwrap(), 0);
+        assert_eq!(album_id(&library, abba), merged_album_id);
+        let album = library.get_track(abba).unwrap().unwrap().album_info;
+        assert_eq!(album.album_artist, Some("Queen".to_string()));
+    }
+
+    #[test]
+    fn libraries_are_imported_without_duplicates() {
+        let old_library = library();
+        let track_id = add_track(&old_library, "Song", "Album", "Artist");
+        add_track(&old_library, "Other Song", "Album", "Artist");
+        let playlist_id = old_library.create_playlist("Playlist".to_string()).unwrap();
+        old_library
+            .add_track_to_playlist(track_id, playlist_id, None)
+            .unwrap();
+
+        let library = library();
+        assert!(library.is_empty().unwrap());
+        library.import(&old_library).unwrap();
+        library.import(&old_library).unwrap();
+        assert!(!library.is_empty().unwrap());
+        let mut titles: Vec<String> = library
+            .tracks()
+            .unwrap()
+            .map(|t| t.track_info.title)
+            .collect();
+        titles.sort();
+        assert_eq!(titles, vec!["Other Song", "Song"]);
+        let playlists: Vec<Playlist> = library.playlists().unwrap().collect();
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlist_titles(&library, playlists[0].id), vec!["Song"]);
+    }
+
+    #[test]
+    fn imports_keep_albums_and_artists_they_cant_tell_apart() {
+        let old_library = library();
+        for (title, artist) in &[("Song", "First"), ("Other Song", "Second")] {
+            let album_id = old_library
+                .create_album(
+                    AlbumInfo {
+                        album_artist: None,
+                        ..album_info("Greatest Hits", "", None)
+                    },
+                    None,
+                )
+                .unwrap();
+            // two different artists who happen to share a name
+            let artist_id = old_library
+                .create_artist(
+                    ArtistInfo {
+                        name: "John Smith".to_string(),
+                        image_url: None,
+                    },
+                    Some(external_id("service", artist)),
+                )
+                .unwrap();
+            old_library
+                .create_track(
+                    TrackInfo {
+                        title: title.to_string(),
+                        isrc: None,
+                        duration_secs: 1.0,
+                        file_path: Some(format!("/music/{}.flac", title)),
+                        track_number: None,
+                        disc_number: None,
+                        genre: None,
+                        composer: None,
+                    },
+                    album_id,
+                    &[(artist_id, ArtistRole::Primary)],
+                    None,
+                )
+                .unwrap();
+        }
+
+        let new_library = library();
+        new_library.import(&old_library).unwrap();
+        new_library.import(&old_library).unwrap();
+        let tracks: Vec<TrackSummary> = new_library.tracks().unwrap().collect();
+        assert_eq!(tracks.len(), 2);
+        assert_ne!(tracks[0].album_id.0, tracks[1].album_id.0);
+        assert_ne!(tracks[0].artist_id.0, tracks[1].artist_id.0);
+        assert_eq!(new_library.albums().unwrap().count(), 2);
+        assert_eq!(new_library.artists().unwrap().count(), 2);
+    }
+
+    #[test]
+    fn dangling_rows_fail_the_import() {
+        let old_library = library();
+        let track_id = add_track(&old_library, "Song", "Album", "Artist");
+        insert_into(track_artists::table)
+            .values(tables::TrackArtist {
+                _id: None,
+                track_id: track_id.0,
+                artist_id: 1000,
+                role: ArtistRole::Featured.to_string(),
+                position: 1,
+            })
+            .execute(old_library.connection().unwrap())
+            .unwrap();
+        let new_library = library();
+        assert!(new_library.import(&old_library).is_err());
+        assert!(new_library.is_empty().unwrap());
+
+        let old_library = library();
+        add_track(&old_library, "Song", "Album", "Artist");
+        insert_into(external_tracks::table)
+            .values(tables::ExternalTrack {
+                _id: None,
+                track_id: 1000,
+                service_id: "service".to_string(),
+                external_id: "track".to_string(),
+            })
+            .execute(old_library.connection().unwrap())
+            .unwrap();
+        let new_library = library();
+        assert!(new_library.import(&old_library).is_err());
+        assert!(new_library.is_empty().unwrap());
+    }
+
+    #[test]
+    fn empty_playlists_are_listed() {
+        let library = library();
+        let playlist = library.create_playlist("Empty".to_string()).unwrap();
+        let found = library.get_playlist(playlist).unwrap().unwrap();
+        assert_eq!(found.name, "Empty");
+        assert_eq!(found.track_count, 0);
+        assert_eq!(found.tracks().count(), 0);
+        let listed: Vec<Playlist> = library.playlists().unwrap().collect();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].id.0, playlist.0);
+    }
+
+    #[test]
+    fn playlists_are_summarized() {
+        let library = library();
+        let track = add_track(&library, "Song", "Album", "Artist");
+        let full = library.create_playlist("Full".to_string()).unwrap();
+        let empty = library.create_playlist("Empty".to_string()).unwrap();
+        library.add_track_to_playlist(track, full, None).unwrap();
+        library.add_track_to_playlist(track, full, None).unwrap();
+
+        let listed: Vec<Playlist> = library.playlists().unwrap().collect();
+        let names: Vec<&str> = listed.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["Full", "Empty"]);
+        assert_eq!(listed[0].track_count, 2);
+        assert_eq!(listed[0].duration_secs, 2.0);
+        assert_eq!(listed[1].track_count, 0);
+        assert_eq!(listed[1].duration_secs, 0.0);
+        assert_eq!(library.get_playlist(full).unwrap().unwrap().track_count, 2);
+        assert_eq!(library.get_playlist(empty).unwrap().unwrap().track_count, 0);
+        assert!(library
+            .get_playlist(LibraryId::new(empty.0 + 1))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn playlists_can_be_renamed_and_deleted() {
+        let library = library();
+        let track = add_track(&library, "Song", "Album", "Artist");
+        let playlist = library.create_playlist("Old".to_string()).unwrap();
+        library
+            .add_track_to_playlist(track, playlist, None)
+            .unwrap();
+        library
+            .rename_playlist(playlist, "New".to_string())
+            .unwrap();
+        assert_eq!(library.get_playlist(playlist).unwrap().unwrap().name, "New");
+
+        library.delete_playlist(playlist).unwrap();
+        assert!(library.get_playlist(playlist).unwrap().is_none());
+        assert!(!library.playlist_exists(playlist).unwrap());
+        assert!(library.delete_playlist(playlist).is_err());
+        assert!(library
+            .rename_playlist(playlist, "Gone".to_string())
+            .is_err());
+    }
+
+    fn external_id<E: Entity>(service: &str, id: &str) -> ExternalId<E> {
+        ExternalId {
+            service: ServiceId(service.to_string()),
+            id: IdString::new(id.to_string()),
+        }
+    }
+
+    fn external_result<E: Entity, T>(service: &str, id: &str, info: T) -> SearchResult<E, T> {
+        SearchResult {
+            library_id: None,
+            external_ids: vec![external_id(service, id)],
+            info,
+        }
+    }
+
+    #[test]
+    fn resolve_links_results_already_in_library() {
+        let library = library();
+        let track_info = || TrackInfo {
+            title: "Song".to_string(),
+            isrc: None,
+            duration_secs: 1.0,
+            file_path: None,
+            track_number: None,
+            disc_number: None,
+            genre: None,
+            composer: None,
+        };
+        let album_info = || AlbumInfo {
+            title: "Album".to_string(),
+            cover_image_url: None,
+            release_date: None,
+            album_artist: None,
+            year: None,
+            musicbrainz_id: None,
+        };
+        let artist_info = || ArtistInfo {
+            name: "Artist".to_string(),
+            image_url: None,
+        };
+        let album_id = library
+            .create_album(album_info(), Some(external_id("service", "album")))
+            .unwrap();
+        let artist_id = library
+            .create_artist(artist_info(), Some(external_id("service", "artist")))
+            .unwrap();
+        let track_id = library
+            .create_track(
+                track_info(),
+                album_id,
+                &[(artist_id, ArtistRole::Primary)],
+                Some(external_id("service", "track")),
+            )
+            .unwrap();
+
+        let results = library
+            .resolve(SearchResults {
+                tracks: vec![
+                    TrackSearchResult {
+                        track: external_result("service", "track", track_info()),
+                        album: external_result("service", "album", album_info()),
+                        artist: external_result("service", "artist", artist_info()),
+                    },
+                    // the same ID on another service is a different track
+                    TrackSearchResult {
+                        track: external_result("other", "track", track_info()),
+                        album: external_result("other", "album", album_info()),
+                        artist: external_result("other", "artist", artist_info()),
+                    },
+                ],
+                artists: vec![external_result("service", "artist", artist_info())],
+                ..SearchResults::default()
+            })
+            .unwrap();
+        let found = &results.tracks[0];
+        assert_eq!(found.track.library_id.map(|id| id.0), Some(track_id.0));
+        assert_eq!(found.album.library_id.map(|id| id.0), Some(album_id.0));
+        assert_eq!(found.artist.library_id.map(|id| id.0), Some(artist_id.0));
+        assert!(results.tracks[1].track.library_id.is_none());
+        assert!(results.tracks[1].artist.library_id.is_none());
+        assert_eq!(
+            results.artists[0].library_id.map(|id| id.0),
+            Some(artist_id.0)
+        );
+    }
+}
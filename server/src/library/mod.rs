@@ -1,11 +1,13 @@
 mod database;
+mod scan;
 mod schema;
 mod tables;
 
 pub use database::Library;
+pub use scan::{scan_directory, ScanSummary};
 
 use crate::ids::{Album, Artist, ExternalId, LibraryId, Track};
-use crate::model::{AlbumInfo, ArtistInfo, TrackInfo};
+use crate::model::{AlbumInfo, ArtistInfo, ArtistRole, TrackInfo};
 
 use serde_derive::Serialize;
 
@@ -16,19 +18,32 @@ pub struct TrackSummary {
     pub track_info: TrackInfo,
     pub artist_id: LibraryId<Artist>,
     pub artist_info: ArtistInfo,
+    /// Every artist credited on the track, primary artists first
+    pub artists: Vec<ArtistCredit>,
     pub album_id: LibraryId<Album>,
     pub album_info: AlbumInfo,
+    /// Whether the track's file has disappeared from disk
+    pub missing: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ArtistCredit {
+    pub artist_id: LibraryId<Artist>,
+    pub artist_info: ArtistInfo,
+    pub role: ArtistRole,
 }
 
 #[derive(Serialize, Clone)]
 pub struct Playlist {
     pub id: LibraryId<crate::ids::Playlist>,
     pub name: String,
+    pub track_count: usize,
+    pub duration_secs: f32,
     track_ids: Vec<LibraryId<Track>>,
 }
 
 impl Playlist {
-    fn tracks(&self) -> impl Iterator<Item = LibraryId<Track>> + '_ {
+    pub fn tracks(&self) -> impl Iterator<Item = LibraryId<Track>> + '_ {
         self.track_ids.iter().copied()
     }
 }
@@ -0,0 +1,226 @@
+use super::Library;
+use crate::api::{Event, EventSink};
+use crate::errors::Try;
+use crate::file_formats;
+use anyhow::Context;
+use fstrings::{f, format_args_f};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use std::fs;
+use std::sync::mpsc;
+use std::thread;
+use walkdir::WalkDir;
+
+#[derive(Serialize, Default)]
+pub struct ScanSummary {
+    pub files_found: usize,
+    pub already_in_library: usize,
+    pub imported: usize,
+    pub failed: usize,
+}
+
+/// Imports every supported file under a directory which isn't in the library yet. Files are read in
+/// parallel, but added to the library one at a time.
+pub fn scan_directory(
+    library: &Library,
+    event_sink: &EventSink,
+    path: &str,
+    recursive: bool,
+) -> Try<ScanSummary> {
+    // file paths are stored as we find them, so they have to be the same however the folder is
+    // spelled, or the files would be imported again
+    let root = fs::canonicalize(path).with_context(|| f!("can't scan {path}"))?;
+    let path = root
+        .to_str()
+        .ok_or_else(|| anyhow!("invalid folder name {:?}", root))?;
+    log::info!("scanning {}", path);
+    let mut file_paths = Vec::new();
+    let mut summary = ScanSummary::default();
+    let walk = WalkDir::new(path).follow_links(true);
+    let walk = if recursive { walk } else { walk.max_depth(1) };
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // such as a folder we aren't allowed into, which shouldn't stop us scanning the rest
+            Err(e) => {
+                let file_path = e.path().unwrap_or(&root).display().to_string();
+                log::warn!("failed to scan {}: {}", file_path, e);
+                summary.failed += 1;
+                event_sink.broadcast(&Event::ScanFileFailed {
+                    file_path,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        if entry.file_type().is_file() && file_formats::is_supported(entry.path()) {
+            match entry.path().to_str() {
+                Some(file_path) => file_paths.push(file_path.to_string()),
+                None => log::warn!("skipping file with invalid name {:?}", entry.path()),
+            }
+        }
+    }
+    summary.files_found = file_paths.len();
+    event_sink.broadcast(&Event::ScanFilesFound {
+        path: path.to_string(),
+        files: summary.files_found,
+    });
+
+    let known_file_paths = library.local_file_paths()?;
+    file_paths.retain(|p| !known_file_paths.contains(p));
+    summary.already_in_library = summary.files_found - file_paths.len();
+
+    let (metadata_tx, metadata_rx) = mpsc::channel();
+    thread::Builder::new()
+        .name("scan thread".to_string())
+        .spawn(move || {
+            file_paths
+                .into_par_iter()
+                .for_each_with(metadata_tx, |metadata_tx, file_path| {
+                    let metadata = file_formats::read_metadata(file_path.clone());
+                    // the receiver only goes away if the scan has failed
+                    let _ = metadata_tx.send((file_path, metadata));
+                })
+        })?;
+    for (file_path, metadata) in metadata_rx {
+        match metadata.and_then(|m| library.add_local_track_metadata(m)) {
+            Ok(track_id) => {
+                summary.imported += 1;
+                event_sink.broadcast(&Event::ScanFileImported {
+                    file_path,
+                    track_id,
+                })
+            }
+            Err(e) => {
+                log::warn!("failed to import {}: {:#}", file_path, e);
+                summary.failed += 1;
+                event_sink.broadcast(&Event::ScanFileFailed {
+                    file_path,
+                    reason: format!("{:#}", e),
+                })
+            }
+        }
+    }
+    log::info!(
+        "scan of {} complete: imported {}, failed {}",
+        path,
+        summary.imported,
+        summary.failed
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Payload;
+    use parking_lot::Mutex;
+    use serde_json::Value;
+    use std::sync::Arc;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/file_formats/fixtures/tagged.m4a"
+    );
+
+    /// Returns the type and file path of each scan event, sorted since files are read in parallel.
+    fn scan_events(events: &Mutex<Vec<Value>>) -> Vec<(String, String)> {
+        let mut events: Vec<(String, String)> = events
+            .lock()
+            .drain(..)
+            .map(|event| {
+                let args = &event["args"];
+                let file_path = args["file_path"]
+                    .as_str()
+                    .map(|p| p.to_string())
+                    .unwrap_or_else(|| args["files"].to_string());
+                (event["type"].as_str().unwrap().to_string(), file_path)
+            })
+            .collect();
+        events.sort();
+        events
+    }
+
+    /// Collects the events broadcast to an event sink.
+    fn collect_events(event_sink: &EventSink) -> Arc<Mutex<Vec<Value>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&events);
+        event_sink.add_destination(Box::new(move |payload: &Payload| {
+            collected
+                .lock()
+                .push(serde_json::from_str(&payload.json).unwrap())
+        }));
+        events
+    }
+
+    #[test]
+    fn scans_report_what_they_imported() {
+        let directory = tempfile::tempdir().unwrap();
+        let directory_path = fs::canonicalize(directory.path()).unwrap();
+        let path = |name: &str| directory_path.join(name).to_str().unwrap().to_string();
+        fs::copy(FIXTURE, path("song.m4a")).unwrap();
+        fs::write(path("notes.txt"), "not music").unwrap();
+        fs::write(path("broken.flac"), "not a flac file").unwrap();
+        fs::create_dir(path("nested")).unwrap();
+        fs::copy(FIXTURE, path("nested/song.m4a")).unwrap();
+
+        let event_sink = EventSink::empty();
+        let events = collect_events(&event_sink);
+        let library =
+            Library::new(path("library.sqlite"), Arc::new(EventSink::empty()), None).unwrap();
+        let root = directory_path.to_str().unwrap();
+
+        let summary = scan_directory(&library, &event_sink, root, false).unwrap();
+        assert_eq!(summary.files_found, 2);
+        assert_eq!(summary.already_in_library, 0);
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.failed, 1);
+        let events = scan_events(&events);
+        assert_eq!(
+            events[0],
+            ("ScanFileFailed".to_string(), path("broken.flac"))
+        );
+        assert_eq!(
+            events[1],
+            ("ScanFileImported".to_string(), path("song.m4a"))
+        );
+        assert_eq!(events[2], ("ScanFilesFound".to_string(), "2".to_string()));
+        assert_eq!(events.len(), 3);
+
+        // files already in the library aren't imported again, however the folder is spelled, but
+        // ones which failed are retried
+        let summary = scan_directory(&library, &event_sink, &path("nested/.."), true).unwrap();
+        assert_eq!(summary.files_found, 3);
+        assert_eq!(summary.already_in_library, 1);
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.failed, 1);
+        let mut file_paths: Vec<String> = library.local_file_paths().unwrap().into_iter().collect();
+        file_paths.sort();
+        assert_eq!(file_paths, vec![path("nested/song.m4a"), path("song.m4a")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_entries_are_reported_and_skipped() {
+        let directory = tempfile::tempdir().unwrap();
+        let directory_path = fs::canonicalize(directory.path()).unwrap();
+        let path = |name: &str| directory_path.join(name).to_str().unwrap().to_string();
+        fs::copy(FIXTURE, path("song.m4a")).unwrap();
+        std::os::unix::fs::symlink(path("nowhere"), path("dangling.m4a")).unwrap();
+
+        let event_sink = EventSink::empty();
+        let events = collect_events(&event_sink);
+        let library =
+            Library::new(path("library.sqlite"), Arc::new(EventSink::empty()), None).unwrap();
+        let summary = scan_directory(&library, &event_sink, &path(""), true).unwrap();
+        assert_eq!(summary.files_found, 1);
+        assert_eq!(summary.imported, 1);
+        assert_eq!(summary.failed, 1);
+        let events = scan_events(&events);
+        assert_eq!(
+            events[0],
+            ("ScanFileFailed".to_string(), path("dangling.m4a"))
+        );
+        assert_eq!(events.len(), 3);
+    }
+}
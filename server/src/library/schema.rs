@@ -4,6 +4,9 @@ table! {
         title -> Text,
         cover_image_url -> Nullable<Text>,
         release_date -> Nullable<Text>,
+        album_artist -> Nullable<Text>,
+        year -> Nullable<Integer>,
+        musicbrainz_id -> Nullable<Text>,
     }
 }
 
@@ -47,6 +50,7 @@ table! {
         _id -> Nullable<BigInt>,
         playlist_id -> BigInt,
         track_id -> BigInt,
+        position -> Integer,
     }
 }
 
@@ -57,6 +61,16 @@ table! {
     }
 }
 
+table! {
+    track_artists (_id) {
+        _id -> Nullable<BigInt>,
+        track_id -> BigInt,
+        artist_id -> BigInt,
+        role -> Text,
+        position -> Integer,
+    }
+}
+
 table! {
     tracks (track_id) {
         track_id -> Nullable<BigInt>,
@@ -66,6 +80,11 @@ table! {
         isrc -> Nullable<Text>,
         duration_secs -> Float,
         file_path -> Nullable<Text>,
+        missing -> Bool,
+        track_number -> Nullable<Integer>,
+        disc_number -> Nullable<Integer>,
+        genre -> Nullable<Text>,
+        composer -> Nullable<Text>,
     }
 }
 
@@ -74,6 +93,8 @@ joinable!(external_artists -> artists (artist_id));
 joinable!(external_tracks -> tracks (track_id));
 joinable!(playlist_tracks -> playlists (playlist_id));
 joinable!(playlist_tracks -> tracks (track_id));
+joinable!(track_artists -> artists (artist_id));
+joinable!(track_artists -> tracks (track_id));
 joinable!(tracks -> albums (album_id));
 joinable!(tracks -> artists (artist_id));
 
@@ -85,5 +106,6 @@ allow_tables_to_appear_in_same_query!(
     external_tracks,
     playlist_tracks,
     playlists,
+    track_artists,
     tracks,
 );
@@ -13,6 +13,9 @@ pub struct Album {
     pub title: String,
     pub cover_image_url: Option<String>,
     pub release_date: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<i32>,
+    pub musicbrainz_id: Option<String>,
 }
 
 #[derive(Identifiable, Queryable, Insertable)]
@@ -56,6 +59,7 @@ pub struct PlaylistTrack {
     pub _id: Option<i64>,
     pub playlist_id: i64,
     pub track_id: i64,
+    pub position: i32,
 }
 
 #[derive(Identifiable, Queryable, Insertable)]
@@ -65,6 +69,16 @@ pub struct Playlist {
     pub name: String,
 }
 
+#[derive(Identifiable, Queryable, Insertable)]
+#[primary_key(_id)]
+pub struct TrackArtist {
+    pub _id: Option<i64>,
+    pub track_id: i64,
+    pub artist_id: i64,
+    pub role: String,
+    pub position: i32,
+}
+
 #[derive(Identifiable, Queryable, Insertable)]
 #[primary_key(track_id)]
 pub struct Track {
@@ -75,5 +89,10 @@ pub struct Track {
     pub isrc: Option<String>,
     pub duration_secs: f32,
     pub file_path: Option<String>,
+    pub missing: bool,
+    pub track_number: Option<i32>,
+    pub disc_number: Option<i32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
 }
 
@@ -0,0 +1,104 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+use yamplayer::config::Config;
+use yamplayer::errors::Try;
+use yamplayer::playback::AudioOutput;
+use yamplayer::server::{import_database, repair_albums, Server};
+
+/// Config file we read when none is given on the command line, if it exists.
+const DEFAULT_CONFIG_PATH: &str = "yamplayer.yml";
+
+/// A music player server. Command line options take precedence over the config file.
+#[derive(StructOpt)]
+#[structopt(name = "yamplayer")]
+struct Args {
+    /// YAML config file
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Address to listen on
+    #[structopt(long)]
+    bind_address: Option<IpAddr>,
+    /// Port to listen on
+    #[structopt(long)]
+    port: Option<u16>,
+    /// Library database file, created if it doesn't exist
+    #[structopt(long)]
+    database_path: Option<String>,
+    /// Folder containing music for the library, can be given more than once
+    #[structopt(long = "library-root")]
+    library_roots: Vec<String>,
+    /// Where to play audio: cpal, null or wav:<path>
+    #[structopt(long)]
+    audio_output: Option<AudioOutput>,
+    /// Log filter, in the same format as RUST_LOG, which it takes precedence over
+    #[structopt(long)]
+    log_level: Option<String>,
+    /// ID of a service to register, can be given more than once
+    #[structopt(long = "service")]
+    services: Vec<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+enum Command {
+    /// Copies the library from an old database file into the configured database, then exits
+    ImportDatabase { old_database_path: String },
+    /// Splits up albums which hold tracks from different albums with the same title, then exits
+    RepairAlbums,
+}
+
+fn main() -> Try<()> {
+    let args = Args::from_args();
+    let config = load_config(&args)?;
+    init_logging(&args, &config);
+    match args.command {
+        Some(Command::ImportDatabase { old_database_path }) => {
+            import_database(config.database_path, old_database_path)
+        }
+        Some(Command::RepairAlbums) => repair_albums(config.database_path),
+        // we don't have any services of our own to offer
+        None => Server::new(config, vec![]).run(),
+    }
+}
+
+/// Logs according to the command line if it sets a filter, or else `RUST_LOG`, or else the config.
+fn init_logging(args: &Args, config: &Config) {
+    match &args.log_level {
+        Some(log_level) => env_logger::Builder::new().parse_filters(log_level).init(),
+        None => {
+            env_logger::from_env(env_logger::Env::default().default_filter_or(&config.log_level))
+                .init()
+        }
+    }
+}
+
+fn load_config(args: &Args) -> Try<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+            Config::load(Path::new(DEFAULT_CONFIG_PATH))?
+        }
+        None => Config::default(),
+    };
+    if let Some(bind_address) = args.bind_address {
+        config.bind_address = bind_address;
+    }
+    if let Some(port) = args.port {
+        config.port = port;
+    }
+    if let Some(database_path) = &args.database_path {
+        config.database_path = database_path.clone();
+    }
+    if !args.library_roots.is_empty() {
+        config.library_roots = args.library_roots.clone();
+    }
+    if let Some(audio_output) = &args.audio_output {
+        config.audio_output = audio_output.clone();
+    }
+    if !args.services.is_empty() {
+        config.services = Some(args.services.clone());
+    }
+    Ok(config)
+}
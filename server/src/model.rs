@@ -1,5 +1,6 @@
 use crate::errors::Try;
-use crate::queue::CurrentTrack;
+use crate::ids::{Album, LibraryId};
+use crate::queue::{CrossfadeCurve, CurrentTrack, RepeatMode};
 use crate::serde::string;
 use crate::services::ServiceId;
 use crate::{deserialize_with_parse, serialize_with_display};
@@ -9,6 +10,7 @@ use serde::export::fmt::Error;
 use serde::export::{Formatter, PhantomData};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::Display;
+use std::io::{Read, Seek};
 use std::str::FromStr;
 use url::Url;
 
@@ -18,6 +20,10 @@ pub struct TrackInfo {
     pub isrc: Option<String>,
     pub duration_secs: f32,
     pub file_path: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -26,16 +32,70 @@ pub struct ArtistInfo {
     pub image_url: Option<Url>,
 }
 
+/// What an artist is credited with on a track.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArtistRole {
+    Primary,
+    Featured,
+    Remixer,
+    Composer,
+}
+
+impl Display for ArtistRole {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        f.write_str(match self {
+            ArtistRole::Primary => "primary",
+            ArtistRole::Featured => "featured",
+            ArtistRole::Remixer => "remixer",
+            ArtistRole::Composer => "composer",
+        })
+    }
+}
+
+impl FromStr for ArtistRole {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Try<Self> {
+        match s {
+            "primary" => Ok(ArtistRole::Primary),
+            "featured" => Ok(ArtistRole::Featured),
+            "remixer" => Ok(ArtistRole::Remixer),
+            "composer" => Ok(ArtistRole::Composer),
+            _ => Err(anyhow!("unknown artist role {}", s)),
+        }
+    }
+}
+
+serialize_with_display!(ArtistRole);
+deserialize_with_parse!(ArtistRole);
+
 #[derive(Serialize, Clone)]
 pub struct AlbumInfo {
     pub title: String,
-    pub cover_image_url: Option<Url>,
+    /// An absolute URL, or for art read from local files, a path on this server
+    pub cover_image_url: Option<String>,
     pub release_date: Option<NaiveDate>,
+    /// Artist credited for the album as a whole. For local files, this is the primary artist of
+    /// the track unless the album artist is tagged.
+    pub album_artist: Option<String>,
+    pub year: Option<i32>,
+    /// MusicBrainz release ID, if the files were tagged with one
+    pub musicbrainz_id: Option<String>,
 }
 
+/// Anything a track can be decoded from, such as its file.
+pub trait TrackReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> TrackReader for T {}
+
+/// Opens a track for reading. It's only called once the track is about to play, and may be called
+/// again, for instance to seek within the track.
+pub type OpenTrack = Box<dyn Fn() -> Try<Box<dyn TrackReader>> + Send + Sync>;
+
 pub struct LoadedTrack {
-    pub data: Vec<u8>,
+    pub open: OpenTrack,
     pub duration_secs: f32,
+    pub album_id: Option<LibraryId<Album>>,
 }
 
 #[derive(Serialize)]
@@ -43,5 +103,9 @@ pub struct PlaybackState {
     pub muted: bool,
     pub volume: f32,
     pub paused: bool,
+    pub crossfade_secs: f32,
+    pub crossfade_curve: CrossfadeCurve,
+    pub shuffled: bool,
+    pub repeat: RepeatMode,
     pub current_track: Option<CurrentTrack>,
 }
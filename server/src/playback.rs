@@ -1,11 +1,92 @@
+use crate::errors::Try;
 use crate::queue::{Queue, QueueCallback};
 use cpal::traits::{DeviceTrait, EventLoopTrait, HostTrait};
+use cpal::{Format, SampleFormat, SampleRate};
 use parking_lot::Mutex;
 use rodio::Sample;
+use serde_derive::Deserialize;
+use std::fs::File;
+use std::io::BufWriter;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn establish<C>(queue_callback: C) -> Arc<Mutex<Queue<f32, C>>>
+/// Format we render audio in when there's no audio device to tell us what it wants.
+const HEADLESS_FORMAT: Format = Format {
+    channels: 2,
+    sample_rate: SampleRate(44100),
+    data_type: SampleFormat::F32,
+};
+
+/// How often a headless output pulls samples from the queue.
+const HEADLESS_TICK: Duration = Duration::from_millis(10);
+
+/// How often a WAV file output updates the header, which means seeking back to the start.
+const WAV_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Where the audio rendered by the queue ends up.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AudioOutput {
+    /// The default output device of the host
+    Cpal,
+    /// Nowhere, but the queue is still played in real time
+    Null,
+    /// A WAV file, written in real time as the queue plays
+    WavFile { path: String },
+}
+
+impl Default for AudioOutput {
+    fn default() -> Self {
+        AudioOutput::Cpal
+    }
+}
+
+impl FromStr for AudioOutput {
+    type Err = anyhow::Error;
+
+    /// Parses `cpal`, `null` or `wav:<path>`
+    fn from_str(s: &str) -> Try<Self> {
+        match s {
+            "cpal" => Ok(AudioOutput::Cpal),
+            "null" => Ok(AudioOutput::Null),
+            _ if s.starts_with("wav:") => Ok(AudioOutput::WavFile {
+                path: s["wav:".len()..].to_string(),
+            }),
+            _ => Err(anyhow!("unknown audio output {}", s)),
+        }
+    }
+}
+
+pub fn establish<C>(
+    output: &AudioOutput,
+    initial_volume: f32,
+    queue_callback: C,
+) -> Try<Arc<Mutex<Queue<f32, C>>>>
+where
+    C: QueueCallback<f32> + Send + 'static,
+{
+    match output {
+        AudioOutput::Cpal => establish_cpal(initial_volume, queue_callback),
+        AudioOutput::Null => establish_headless(NullSink, initial_volume, queue_callback),
+        AudioOutput::WavFile { path } => {
+            log::info!("writing audio to {}", path);
+            let writer = hound::WavWriter::create(
+                path,
+                hound::WavSpec {
+                    channels: HEADLESS_FORMAT.channels,
+                    sample_rate: HEADLESS_FORMAT.sample_rate.0,
+                    bits_per_sample: 32,
+                    sample_format: hound::SampleFormat::Float,
+                },
+            )?;
+            establish_headless(WavFileSink::new(writer), initial_volume, queue_callback)
+        }
+    }
+}
+
+fn establish_cpal<C>(initial_volume: f32, queue_callback: C) -> Try<Arc<Mutex<Queue<f32, C>>>>
 where
     C: QueueCallback<f32> + Send + 'static,
 {
@@ -13,23 +94,26 @@ where
     let event_loop = host.event_loop();
     let device = host
         .default_output_device()
-        .expect("no audio output device found");
+        .ok_or_else(|| anyhow!("no audio output device found"))?;
     let mut supported_formats_range = device
         .supported_output_formats()
-        .expect("error while querying formats");
+        .map_err(|e| anyhow!("error while querying formats: {}", e))?;
     let format = supported_formats_range
         .next()
-        .expect("no supported format?!")
+        .ok_or_else(|| anyhow!("no supported format?!"))?
         .with_max_sample_rate();
     let stream_id = event_loop
         .build_output_stream(&device, &format)
-        .expect("error building audio stream");
+        .map_err(|e| anyhow!("error building audio stream: {}", e))?;
     event_loop
         .play_stream(stream_id)
-        .expect("failed to play audio stream");
+        .map_err(|e| anyhow!("failed to play audio stream: {}", e))?;
 
-    let volume = 0.5_f32;
-    let queue = Arc::new(Mutex::new(Queue::new(volume, format, queue_callback)));
+    let queue = Arc::new(Mutex::new(Queue::new(
+        initial_volume,
+        format,
+        queue_callback,
+    )));
     let queue_for_audio_thread = Arc::clone(&queue);
 
     thread::Builder::new()
@@ -45,10 +129,9 @@ where
                 };
                 audio_callback(stream_data, &queue_for_audio_thread)
             });
-        })
-        .expect("error spawning audio thread");
+        })?;
 
-    queue
+    Ok(queue)
 }
 
 fn audio_callback(stream_data: cpal::StreamData, audio_source: &Mutex<impl Iterator<Item = f32>>) {
@@ -64,3 +147,113 @@ fn audio_callback(stream_data: cpal::StreamData, audio_source: &Mutex<impl Itera
         _ => panic!("we only support playing f32 samples"),
     }
 }
+
+/// Receives the audio rendered by a headless output.
+trait SampleSink: Send + 'static {
+    fn write(&mut self, samples: &[f32]) -> Try<()>;
+}
+
+struct NullSink;
+
+impl SampleSink for NullSink {
+    fn write(&mut self, _samples: &[f32]) -> Try<()> {
+        Ok(())
+    }
+}
+
+struct WavFileSink {
+    /// Only taken when the sink is dropped, to finalize the file
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+    last_flushed: Instant,
+}
+
+impl WavFileSink {
+    fn new(writer: hound::WavWriter<BufWriter<File>>) -> Self {
+        WavFileSink {
+            writer: Some(writer),
+            last_flushed: Instant::now(),
+        }
+    }
+}
+
+impl SampleSink for WavFileSink {
+    fn write(&mut self, samples: &[f32]) -> Try<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        for sample in samples {
+            writer.write_sample(*sample)?;
+        }
+        // keep the header fairly up to date so the file is playable while we're still writing it
+        if self.last_flushed.elapsed() >= WAV_FLUSH_INTERVAL {
+            writer.flush()?;
+            self.last_flushed = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WavFileSink {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finalize() {
+                log::error!("error finalizing WAV file: {}", e);
+            }
+        }
+    }
+}
+
+/// Plays the queue without an audio device, using a timer to pull samples from it in real time.
+/// Playback stops, and the sink is dropped, once nothing else holds on to the queue.
+fn establish_headless<C>(
+    mut sink: impl SampleSink,
+    initial_volume: f32,
+    queue_callback: C,
+) -> Try<Arc<Mutex<Queue<f32, C>>>>
+where
+    C: QueueCallback<f32> + Send + 'static,
+{
+    let queue = Arc::new(Mutex::new(Queue::new(
+        initial_volume,
+        HEADLESS_FORMAT,
+        queue_callback,
+    )));
+    let queue_for_audio_thread = Arc::downgrade(&queue);
+    let samples_per_sec =
+        f64::from(HEADLESS_FORMAT.channels) * f64::from(HEADLESS_FORMAT.sample_rate.0);
+    let channels = u64::from(HEADLESS_FORMAT.channels);
+
+    thread::Builder::new()
+        .name("audio thread".to_string())
+        .spawn(move || {
+            let started = Instant::now();
+            let mut samples_rendered = 0_u64;
+            let mut buffer = Vec::new();
+            loop {
+                thread::sleep(HEADLESS_TICK);
+                // catch up with the clock, rather than trusting sleep to be accurate
+                let samples_due = (started.elapsed().as_secs_f64() * samples_per_sec) as u64
+                    / channels
+                    * channels;
+                buffer.resize((samples_due - samples_rendered) as usize, 0.0);
+                {
+                    let queue = match queue_for_audio_thread.upgrade() {
+                        Some(queue) => queue,
+                        None => return,
+                    };
+                    let mut audio_source = queue.lock();
+                    for slot in buffer.iter_mut() {
+                        *slot = audio_source.next().unwrap_or_else(Sample::zero_value);
+                    }
+                }
+                samples_rendered = samples_due;
+                if let Err(e) = sink.write(&buffer) {
+                    log::error!("error writing audio, stopping playback: {}", e);
+                    return;
+                }
+            }
+        })?;
+
+    Ok(queue)
+}
@@ -1,14 +1,17 @@
-use crate::api::Event::{PlaybackChanged, VolumeChanged};
+use crate::api::Event::{CrossfadeChanged, PlaybackChanged, VolumeChanged};
 use crate::api::{Event, EventSink};
 use crate::errors::Try;
+use crate::file_formats;
 use crate::ids::{Id, Track};
-use crate::model::{LoadedTrack, PlaybackState};
+use crate::model::{LoadedTrack, OpenTrack, PlaybackState};
 use crate::playback;
-use crate::queue::{Queue, QueueCallback};
+use crate::playback::AudioOutput;
+use crate::queue::{
+    CrossfadeCurve, EnqueueMode, EnqueuedTrack, EntryMarker, NewEntry, Queue, QueueCallback,
+    RepeatMode, SourceFactory,
+};
 use log;
 use parking_lot::Mutex;
-use rodio::decoder::Decoder;
-use std::io::Cursor;
 use std::sync::Arc;
 
 pub struct PlayerApp {
@@ -22,19 +25,26 @@ struct QueueCallbackHandler {
 
 impl QueueCallback<f32> for QueueCallbackHandler {
     fn on_current_track_changed(&self, queue: &Queue<f32, Self>) {
-        self.event_sink.broadcast(&Event::PlaybackChanged {
-            paused: queue.controls.paused,
-            current_track: queue.current_track(),
+        self.event_sink.broadcast(&playback_changed(queue));
+    }
+
+    fn on_queue_changed(&self, queue: &Queue<f32, Self>) {
+        self.event_sink.broadcast(&Event::QueueChanged {
+            tracks: queue.tracks().cloned().collect(),
         });
     }
 }
 
 impl PlayerApp {
-    pub fn new(event_sink: Arc<EventSink>) -> Try<PlayerApp> {
+    pub fn new(
+        event_sink: Arc<EventSink>,
+        audio_output: &AudioOutput,
+        initial_volume: f32,
+    ) -> Try<PlayerApp> {
         let callback = QueueCallbackHandler {
             event_sink: Arc::clone(&event_sink),
         };
-        let queue = playback::establish(callback);
+        let queue = playback::establish(audio_output, initial_volume, callback)?;
         Ok(PlayerApp { queue, event_sink })
     }
 
@@ -44,6 +54,10 @@ impl PlayerApp {
             muted: q.controls.muted,
             volume: q.controls.volume,
             paused: q.controls.paused,
+            crossfade_secs: q.controls.crossfade_secs,
+            crossfade_curve: q.controls.crossfade_curve,
+            shuffled: q.is_shuffled(),
+            repeat: q.controls.repeat,
             current_track: q.current_track(),
         }
     }
@@ -62,14 +76,25 @@ impl PlayerApp {
         })
     }
 
+    pub fn update_crossfade(&self, duration_secs: Option<f32>, curve: Option<CrossfadeCurve>) {
+        let mut queue = self.queue.lock();
+        if let Some(new_duration_secs) = duration_secs {
+            queue.controls.crossfade_secs = new_duration_secs.max(0.0);
+        }
+        if let Some(new_curve) = curve {
+            queue.controls.crossfade_curve = new_curve;
+        }
+        self.event_sink.broadcast(&CrossfadeChanged {
+            duration_secs: queue.controls.crossfade_secs,
+            curve: queue.controls.crossfade_curve,
+        })
+    }
+
     pub fn unpause(&self) {
         let mut queue = self.queue.lock();
         if queue.controls.paused {
             queue.controls.paused = false;
-            self.event_sink.broadcast(&PlaybackChanged {
-                paused: false,
-                current_track: queue.current_track(),
-            })
+            self.event_sink.broadcast(&playback_changed(&queue))
         }
     }
 
@@ -77,32 +102,214 @@ impl PlayerApp {
         let mut source = self.queue.lock();
         if !source.controls.paused {
             source.controls.paused = true;
-            self.event_sink.broadcast(&PlaybackChanged {
-                paused: true,
-                current_track: source.current_track(),
-            })
+            self.event_sink.broadcast(&playback_changed(&source))
         }
     }
 
+    pub fn toggle_shuffle(&self) {
+        let mut queue = self.queue.lock();
+        let shuffled = queue.is_shuffled();
+        queue.set_shuffled(!shuffled);
+        self.event_sink.broadcast(&playback_changed(&queue))
+    }
+
+    /// Switches to the given repeat mode, or turns repeat off if it's already in that mode.
+    pub fn toggle_repeat(&self, mode: RepeatMode) {
+        let mut queue = self.queue.lock();
+        queue.controls.repeat = if queue.controls.repeat == mode {
+            RepeatMode::Off
+        } else {
+            mode
+        };
+        self.event_sink.broadcast(&playback_changed(&queue))
+    }
+
     pub fn skip_to_next(&self) {
         self.queue.lock().skip_current();
     }
 
+    pub fn skip_to_previous(&self) -> Try<()> {
+        self.queue.lock().skip_to_previous()
+    }
+
+    pub fn seek(&self, position_secs: f32) -> Try<()> {
+        let mut queue = self.queue.lock();
+        queue.seek(position_secs)?;
+        self.event_sink.broadcast(&playback_changed(&queue));
+        Ok(())
+    }
+
     pub fn add_to_queue(&self, track_id: Id<Track>, track: LoadedTrack) -> Try<()> {
-        let source: Decoder<_> = Decoder::new(Cursor::new(track.data))?;
-        log::info!(
-            "enqueuing track {} with length: {}:{:02}",
-            track_id,
-            track.duration_secs as i64 / 60,
-            track.duration_secs as i64 % 60
-        );
+        let source = track_source(&track_id, track.open, track.duration_secs);
+        self.queue
+            .lock()
+            .enqueue_last(track_id, track.duration_secs, track.album_id, source)?;
+        Ok(())
+    }
+
+    /// Adds a track right after the current one, or plays it straight away if nothing is playing.
+    pub fn add_next_in_queue(&self, track_id: Id<Track>, track: LoadedTrack) -> Try<()> {
+        let source = track_source(&track_id, track.open, track.duration_secs);
         self.queue
             .lock()
-            .enqueue_last(track_id, track.duration_secs, Box::new(source));
+            .enqueue_next(track_id, track.duration_secs, track.album_id, source)?;
+        Ok(())
+    }
+
+    /// Adds several tracks to the queue at once, so that nothing else can get in between them.
+    pub fn add_all_to_queue(
+        &self,
+        tracks: Vec<(Id<Track>, LoadedTrack)>,
+        mode: EnqueueMode,
+    ) -> Try<()> {
+        let entries = tracks
+            .into_iter()
+            .map(|(track_id, track)| NewEntry {
+                source: track_source(&track_id, track.open, track.duration_secs),
+                id: track_id,
+                duration_secs: track.duration_secs,
+                album_id: track.album_id,
+            })
+            .collect();
+        self.queue.lock().enqueue_all(entries, mode)?;
         Ok(())
     }
 
+    pub fn queue(&self) -> Vec<EnqueuedTrack> {
+        self.queue.lock().tracks().cloned().collect()
+    }
+
+    /// Returns the tracks played recently, most recent first.
+    pub fn history(&self) -> Vec<EnqueuedTrack> {
+        self.queue.lock().history().cloned().collect()
+    }
+
+    // these return true iff the entry was in the queue
+
+    pub fn remove_from_queue(&self, entry_marker: EntryMarker) -> bool {
+        self.queue.lock().remove(entry_marker)
+    }
+
+    pub fn move_in_queue(&self, entry_marker: EntryMarker, index: usize) -> bool {
+        self.queue.lock().move_entry(entry_marker, index)
+    }
+
+    pub fn jump_to(&self, entry_marker: EntryMarker) -> bool {
+        self.queue.lock().jump_to(entry_marker)
+    }
+
     pub fn empty_queue(&self) {
         self.queue.lock().clear();
     }
 }
+
+fn playback_changed(queue: &Queue<f32, QueueCallbackHandler>) -> Event {
+    PlaybackChanged {
+        paused: queue.controls.paused,
+        shuffled: queue.is_shuffled(),
+        repeat: queue.controls.repeat,
+        current_track: queue.current_track(),
+    }
+}
+
+fn track_source(track_id: &Id<Track>, open: OpenTrack, duration_secs: f32) -> SourceFactory<i16> {
+    log::info!(
+        "enqueuing track {} with length: {}:{:02}",
+        track_id,
+        duration_secs as i64 / 60,
+        duration_secs as i64 % 60
+    );
+    // the track is opened again for every decoder we create, e.g. when seeking
+    Box::new(move || file_formats::decode(open()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Payload;
+    use crate::ids::LibraryId;
+    use crate::model::TrackReader;
+    use std::io::Cursor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn silent_wav(samples: usize) -> Vec<u8> {
+        let mut wav = Cursor::new(Vec::new());
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for _ in 0..samples {
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        wav.into_inner()
+    }
+
+    /// Opens a WAV file from memory, counting how many times it's opened.
+    fn open_wav(wav: Vec<u8>, opened: Arc<AtomicUsize>) -> OpenTrack {
+        Box::new(move || -> Try<Box<dyn TrackReader>> {
+            opened.fetch_add(1, Ordering::SeqCst);
+            Ok(Box::new(Cursor::new(wav.clone())))
+        })
+    }
+
+    #[test]
+    fn plays_queue_without_an_audio_device() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let event_sink = Arc::new(EventSink::empty());
+        let events_for_sink = Arc::clone(&events);
+        event_sink.add_destination(Box::new(move |payload: &Payload| {
+            events_for_sink.lock().push(payload.json.clone())
+        }));
+        let player = PlayerApp::new(event_sink, &AudioOutput::Null, 0.5).unwrap();
+        let track = LoadedTrack {
+            open: open_wav(silent_wav(8820), Arc::new(AtomicUsize::new(0))),
+            duration_secs: 0.2,
+            album_id: None,
+        };
+        player
+            .add_to_queue(Id::Library(LibraryId::new(1)), track)
+            .unwrap();
+        assert!(player.playback_state().current_track.is_some());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while player.playback_state().current_track.is_some() {
+            assert!(Instant::now() < deadline, "track never finished playing");
+            thread::sleep(Duration::from_millis(10));
+        }
+        // one event when the track started, and another when it finished
+        let playback_events = events
+            .lock()
+            .iter()
+            .filter(|json| json.contains("PlaybackChanged"))
+            .count();
+        assert_eq!(playback_events, 2);
+    }
+
+    #[test]
+    fn only_tracks_near_the_front_are_opened() {
+        let player = PlayerApp::new(Arc::new(EventSink::empty()), &AudioOutput::Null, 0.5).unwrap();
+        let opened = Arc::new(AtomicUsize::new(0));
+        for id in 1..=5 {
+            let track = LoadedTrack {
+                open: open_wav(silent_wav(44100 * 10), Arc::clone(&opened)),
+                duration_secs: 10.0,
+                album_id: None,
+            };
+            player
+                .add_to_queue(Id::Library(LibraryId::new(id)), track)
+                .unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while opened.load(Ordering::SeqCst) < 2 {
+            assert!(Instant::now() < deadline, "tracks were never opened");
+            thread::sleep(Duration::from_millis(10));
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(opened.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,226 @@
+use crate::errors::Try;
+use crossbeam::channel::{bounded, Receiver, TryRecvError};
+use rodio::{Sample, Source};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::vec;
+
+/// Number of samples the decoder thread hands over to the audio thread at a time.
+const CHUNK_SAMPLES: usize = 4096;
+/// Number of chunks the decoder thread may get ahead of playback.
+const CHUNKS_AHEAD: usize = 32;
+
+/// Creates the source to decode. It's called on the decoder thread, so that opening the track
+/// doesn't hold up whoever starts it.
+pub type OpenSource<S> = Box<dyn FnOnce() -> Try<Box<dyn Source<Item = S> + Send>> + Send>;
+
+/// A source that is opened and decoded (and resampled) on a background thread once started, so
+/// that the audio thread only has to copy samples out of chunks that are already prepared.
+pub struct Preroll<S> {
+    state: State<S>,
+}
+
+/// What a preroll has to offer when it's asked for a sample.
+pub enum Polled<S> {
+    Sample(S),
+    /// The decoder hasn't caught up with playback, so there's nothing to play just yet
+    Underrun,
+    Finished,
+}
+
+enum State<S> {
+    Idle {
+        open: OpenSource<S>,
+        skip_samples: u64,
+    },
+    Running {
+        chunks: Receiver<Vec<S>>,
+        current: vec::IntoIter<S>,
+        /// Set when we no longer want the decoder thread's samples
+        cancelled: Arc<AtomicBool>,
+    },
+    Finished,
+}
+
+impl<S> Preroll<S>
+where
+    S: Sample + Send + 'static,
+{
+    pub fn new(open: OpenSource<S>) -> Self {
+        Self::starting_at(open, 0)
+    }
+
+    /// Creates a source that discards the given number of samples before producing any.
+    pub fn starting_at(open: OpenSource<S>, skip_samples: u64) -> Self {
+        Preroll {
+            state: State::Idle { open, skip_samples },
+        }
+    }
+
+    /// Starts decoding in the background, if that hasn't happened already.
+    pub fn start(&mut self) {
+        if let State::Idle { .. } = self.state {
+            if let State::Idle { open, skip_samples } =
+                mem::replace(&mut self.state, State::Finished)
+            {
+                self.state = spawn_decoder(open, skip_samples);
+            }
+        }
+    }
+
+    /// Returns the next sample if it has been decoded already. This never waits for the decoder,
+    /// since it's called on the audio thread.
+    pub fn poll(&mut self) -> Polled<S> {
+        loop {
+            let next_chunk = match &mut self.state {
+                State::Idle { .. } => None,
+                State::Running {
+                    chunks, current, ..
+                } => {
+                    if let Some(sample) = current.next() {
+                        return Polled::Sample(sample);
+                    }
+                    Some(chunks.try_recv())
+                }
+                State::Finished => return Polled::Finished,
+            };
+            match next_chunk {
+                // we weren't started ahead of time, so start now
+                None => self.start(),
+                Some(Ok(chunk)) => {
+                    if let State::Running { current, .. } = &mut self.state {
+                        *current = chunk.into_iter();
+                    }
+                }
+                Some(Err(TryRecvError::Empty)) => return Polled::Underrun,
+                // the decoder thread has hung up, so the source is exhausted
+                Some(Err(TryRecvError::Disconnected)) => self.state = State::Finished,
+            }
+        }
+    }
+}
+
+fn spawn_decoder<S>(open: OpenSource<S>, skip_samples: u64) -> State<S>
+where
+    S: Sample + Send + 'static,
+{
+    let (chunks_tx, chunks_rx) = bounded(CHUNKS_AHEAD);
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancelled_for_decoder = Arc::clone(&cancelled);
+    thread::Builder::new()
+        .name("decoder thread".to_string())
+        .spawn(move || {
+            // if the track can't be opened, it ends straight away and the next one plays
+            let mut source = match open() {
+                Ok(source) => source,
+                Err(e) => {
+                    log::warn!("failed to open track for playback: {:#}", e);
+                    return;
+                }
+            };
+            // skipping a long way into a track takes a while, so check whether we're still wanted
+            let mut skipped = 0;
+            while skipped < skip_samples {
+                if cancelled_for_decoder.load(Ordering::Relaxed) {
+                    return;
+                }
+                let to_skip = (skip_samples - skipped).min(CHUNK_SAMPLES as u64) as usize;
+                let count = source.by_ref().take(to_skip).count();
+                if count == 0 {
+                    break;
+                }
+                skipped += count as u64;
+            }
+            loop {
+                let chunk: Vec<S> = source.by_ref().take(CHUNK_SAMPLES).collect();
+                // the receiver goes away when the track is removed from the queue, so stop decoding
+                if chunk.is_empty() || chunks_tx.send(chunk).is_err() {
+                    break;
+                }
+            }
+        })
+        .expect("error spawning decoder thread");
+    State::Running {
+        chunks: chunks_rx,
+        current: Vec::new().into_iter(),
+        cancelled,
+    }
+}
+
+impl<S> Drop for Preroll<S> {
+    fn drop(&mut self) {
+        // the decoder thread notices the channel is gone as soon as it sends a chunk, but not while
+        // it's skipping, for instance to where we seeked
+        if let State::Running { cancelled, .. } = &self.state {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam::channel::{RecvTimeoutError, Sender};
+    use std::time::Duration;
+
+    /// Endless silence, which says when the first sample is taken from it. Its other sender is
+    /// dropped along with it, which disconnects the receiver.
+    struct Silence {
+        started: Option<Sender<()>>,
+        _dropped: Sender<()>,
+    }
+
+    impl Iterator for Silence {
+        type Item = f32;
+
+        fn next(&mut self) -> Option<f32> {
+            if let Some(started) = self.started.take() {
+                started.send(()).unwrap();
+            }
+            Some(0.0)
+        }
+    }
+
+    impl Source for Silence {
+        fn current_frame_len(&self) -> Option<usize> {
+            None
+        }
+
+        fn channels(&self) -> u16 {
+            1
+        }
+
+        fn sample_rate(&self) -> u32 {
+            44100
+        }
+
+        fn total_duration(&self) -> Option<Duration> {
+            None
+        }
+    }
+
+    #[test]
+    fn dropping_stops_the_decoder_while_it_skips() {
+        let (started_tx, started_rx) = bounded(1);
+        let (dropped_tx, dropped_rx) = bounded::<()>(0);
+        let mut preroll: Preroll<f32> = Preroll::starting_at(
+            Box::new(move || -> Try<Box<dyn Source<Item = f32> + Send>> {
+                Ok(Box::new(Silence {
+                    started: Some(started_tx),
+                    _dropped: dropped_tx,
+                }))
+            }),
+            u64::max_value(),
+        );
+        preroll.start();
+        started_rx.recv().unwrap();
+        drop(preroll);
+        // the decoder thread drops the source when it stops, and would skip forever otherwise
+        assert_eq!(
+            dropped_rx.recv_timeout(Duration::from_secs(10)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
@@ -1,14 +1,36 @@
-use crate::ids::{Id, Track};
+use crate::errors::Try;
+use crate::ids::{Album, Id, LibraryId, Track};
+use crate::preroll::{OpenSource, Polled, Preroll};
 use crate::serde::string;
 use cpal::Format;
+use rand::seq::SliceRandom;
 use rodio::source::UniformSourceIterator;
 use rodio::{Sample, Source};
-use serde_derive::Serialize;
-use std::collections::VecDeque;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+
+/// How many entries at the front of the queue are decoded ahead of playback.
+const PREROLL_ENTRIES: usize = 2;
+
+/// How many played tracks we remember.
+const HISTORY_ENTRIES: usize = 100;
+
+/// How far into a track skipping to the previous one restarts this one instead.
+const RESTART_AFTER_SECS: f32 = 3.0;
+
+/// Creates a decoder positioned at the start of a track. It may be called more than once, for
+/// instance to seek within the track.
+pub type SourceFactory<T> = Box<dyn Fn() -> Try<Box<dyn Source<Item = T> + Send>> + Send + Sync>;
 
 pub struct Queue<S, C> {
     tracks: VecDeque<QueueItem<S>>,
+    /// Tracks which finished or were skipped, most recent last
+    history: VecDeque<PlayedItem<S>>,
     next_entry_marker: u64,
+    /// Every entry in the queue in the order it was in when it was shuffled, if it's shuffled
+    unshuffled_order: Option<Vec<EntryMarker>>,
     audio_format: Format,
     callback: C,
     pub controls: PlaybackControls,
@@ -18,6 +40,57 @@ pub struct PlaybackControls {
     pub paused: bool,
     pub muted: bool,
     pub volume: f32,
+    pub crossfade_secs: f32,
+    pub crossfade_curve: CrossfadeCurve,
+    pub repeat: RepeatMode,
+}
+
+/// What happens when the current track finishes.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum RepeatMode {
+    /// Move on to the next track and forget this one
+    Off,
+    /// Play this track again
+    One,
+    /// Move on to the next track, putting this one at the end of the queue
+    All,
+}
+
+/// Where in the queue new tracks go.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum EnqueueMode {
+    /// Instead of everything in the queue, including the current track
+    Replace,
+    /// Right after the current track
+    Next,
+    /// At the end
+    Last,
+}
+
+/// A track to add to the queue.
+pub struct NewEntry<T> {
+    pub id: Id<Track>,
+    pub duration_secs: f32,
+    pub album_id: Option<LibraryId<Album>>,
+    pub source: SourceFactory<T>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CrossfadeCurve {
+    Linear,
+    EqualPower,
+}
+
+impl CrossfadeCurve {
+    /// Returns the gains of the outgoing and incoming tracks, given how far through the fade we are
+    fn gains(self, progress: f32) -> (f32, f32) {
+        match self {
+            CrossfadeCurve::Linear => (1.0 - progress, progress),
+            CrossfadeCurve::EqualPower => {
+                ((progress * FRAC_PI_2).cos(), (progress * FRAC_PI_2).sin())
+            }
+        }
+    }
 }
 
 pub trait QueueCallback<S>
@@ -25,6 +98,8 @@ where
     Self: Sized,
 {
     fn on_current_track_changed(&self, queue: &Queue<S, Self>);
+    /// Called whenever entries are added to, removed from or moved within the queue
+    fn on_queue_changed(&self, queue: &Queue<S, Self>);
 }
 
 impl<S, C> Queue<S, C>
@@ -35,13 +110,18 @@ where
     pub fn new(initial_volume: f32, audio_format: Format, callback: C) -> Self {
         Queue {
             tracks: VecDeque::new(),
+            history: VecDeque::new(),
             next_entry_marker: 0,
+            unshuffled_order: None,
             audio_format,
             callback,
             controls: PlaybackControls {
                 paused: false,
                 muted: false,
                 volume: initial_volume,
+                crossfade_secs: 0.0,
+                crossfade_curve: CrossfadeCurve::EqualPower,
+                repeat: RepeatMode::Off,
             },
         }
     }
@@ -50,110 +130,418 @@ where
         &mut self,
         id: Id<Track>,
         duration_secs: f32,
-        source: Box<dyn Source<Item = T> + Send>,
-    ) -> EntryMarker {
-        let t = self.create_track(id, duration_secs, source);
-        let entry_marker = t.track.entry_marker;
-        self.tracks.push_back(t);
-        if self.tracks.len() == 1 {
-            self.raise_track_changed();
-        }
-        entry_marker
+        album_id: Option<LibraryId<Album>>,
+        source: SourceFactory<T>,
+    ) -> Try<EntryMarker> {
+        let entry = NewEntry {
+            id,
+            duration_secs,
+            album_id,
+            source,
+        };
+        Ok(self.enqueue_all(vec![entry], EnqueueMode::Last)?[0])
     }
 
     pub fn enqueue_next<T: Sample + Send + 'static>(
         &mut self,
         id: Id<Track>,
         duration_secs: f32,
-        source: Box<dyn Source<Item = T> + Send>,
-    ) -> EntryMarker {
-        let t = self.create_track(id, duration_secs, source);
-        let entry_marker = t.track.entry_marker;
-        if self.tracks.is_empty() {
-            self.tracks.push_front(t);
+        album_id: Option<LibraryId<Album>>,
+        source: SourceFactory<T>,
+    ) -> Try<EntryMarker> {
+        let entry = NewEntry {
+            id,
+            duration_secs,
+            album_id,
+            source,
+        };
+        Ok(self.enqueue_all(vec![entry], EnqueueMode::Next)?[0])
+    }
+
+    /// Adds several tracks at once, keeping them together and in order, and returns their entry
+    /// markers.
+    pub fn enqueue_all<T: Sample + Send + 'static>(
+        &mut self,
+        entries: Vec<NewEntry<T>>,
+        mode: EnqueueMode,
+    ) -> Try<Vec<EntryMarker>> {
+        let mut items = Vec::with_capacity(entries.len());
+        for entry in entries {
+            items.push(self.create_track(
+                entry.id,
+                entry.duration_secs,
+                entry.album_id,
+                entry.source,
+            )?);
+        }
+        let entry_markers = items.iter().map(|t| t.track.entry_marker).collect();
+        let current = self.tracks.front().map(|t| t.track.entry_marker);
+        match mode {
+            EnqueueMode::Replace => {
+                self.tracks.clear();
+                self.tracks.extend(items);
+            }
+            EnqueueMode::Next => {
+                let start = self.tracks.len().min(1);
+                for (i, item) in items.into_iter().enumerate() {
+                    self.tracks.insert(start + i, item);
+                }
+            }
+            EnqueueMode::Last => self.tracks.extend(items),
+        }
+        self.preroll();
+        if self.tracks.front().map(|t| t.track.entry_marker) != current {
             self.raise_track_changed();
-        } else {
-            self.tracks.insert(1, t);
         }
-        entry_marker
+        self.raise_queue_changed();
+        Ok(entry_markers)
     }
 
     fn create_track<T: Sample + Send + 'static>(
         &mut self,
         id: Id<Track>,
         duration_secs: f32,
-        source: Box<dyn Source<Item = T> + Send>,
-    ) -> QueueItem<S> {
-        let entry_marker = EntryMarker(self.next_entry_marker);
-        self.next_entry_marker += 1;
-        // converter that interpolates the input track samples producing the right output format
-        let mixed_source = UniformSourceIterator::new(
-            source,
-            self.audio_format.channels,
-            self.audio_format.sample_rate.0,
-        );
-        QueueItem {
+        album_id: Option<LibraryId<Album>>,
+        source: SourceFactory<T>,
+    ) -> Try<QueueItem<S>> {
+        let (channels, sample_rate) = (self.audio_format.channels, self.audio_format.sample_rate.0);
+        let mixed_source: SourceFactory<S> =
+            Box::new(move || -> Try<Box<dyn Source<Item = S> + Send>> {
+                // converter that interpolates the input track samples producing the right output format
+                Ok(Box::new(UniformSourceIterator::new(
+                    source()?,
+                    channels,
+                    sample_rate,
+                )))
+            });
+        let mixed_source = Arc::new(mixed_source);
+        // nothing is opened until the track is prerolled, near the front of the queue
+        let audio_source = CountedSource::new(Preroll::new(open_source(&mixed_source)));
+        Ok(QueueItem {
             track: EnqueuedTrack {
                 id,
                 duration_secs,
-                entry_marker,
+                entry_marker: self.new_entry_marker(),
             },
-            audio_source: CountedSource::new(Box::new(mixed_source)),
-        }
+            album_id,
+            source_factory: mixed_source,
+            audio_source,
+        })
+    }
+
+    fn new_entry_marker(&mut self) -> EntryMarker {
+        let entry_marker = EntryMarker(self.next_entry_marker);
+        self.next_entry_marker += 1;
+        entry_marker
+    }
+
+    /// Moves playback of the current track to the given position, by decoding it again from the
+    /// start and discarding everything before that position.
+    pub fn seek(&mut self, position_secs: f32) -> Try<()> {
+        let channels = u64::from(self.audio_format.channels);
+        // keep the position on a frame boundary so the channels don't get swapped
+        let position_samples =
+            (position_secs.max(0.0) * self.samples_per_sec()) as u64 / channels * channels;
+        let current = self
+            .tracks
+            .get_mut(0)
+            .ok_or_else(|| anyhow!("no track is playing"))?;
+        let mut audio_source = CountedSource {
+            samples_played: position_samples,
+            inner: Preroll::starting_at(open_source(&current.source_factory), position_samples),
+        };
+        audio_source.inner.start();
+        current.audio_source = audio_source;
+        Ok(())
     }
 
     pub fn skip_current(&mut self) -> Option<EnqueuedTrack> {
-        let popped = self.tracks.pop_front().map(|t| t.track);
+        let skipped = self.tracks.pop_front().map(|item| {
+            self.remember(&item);
+            let track = item.track.clone();
+            // a skipped track stays in the rotation, but isn't played again straight away
+            if self.controls.repeat == RepeatMode::All {
+                self.tracks.push_back(item.restarted());
+            }
+            track
+        });
+        self.preroll();
+        self.raise_track_changed();
+        self.raise_queue_changed();
+        skipped
+    }
+
+    /// Moves on from the current track once it has finished playing, according to the repeat
+    /// mode.
+    fn finish_current(&mut self) {
+        let finished = match self.tracks.pop_front() {
+            Some(finished) => finished,
+            None => return,
+        };
+        // tracks which fail to play are dropped rather than repeated, or we'd spin on them forever
+        let played = finished.audio_source.samples_played > 0;
+        let repeat = if played {
+            self.controls.repeat
+        } else {
+            RepeatMode::Off
+        };
+        if played && repeat != RepeatMode::One {
+            self.remember(&finished);
+        }
+        match repeat {
+            RepeatMode::Off => {}
+            RepeatMode::One => self.tracks.push_front(finished.restarted()),
+            RepeatMode::All => self.tracks.push_back(finished.restarted()),
+        }
+        self.preroll();
+        self.raise_track_changed();
+        if repeat != RepeatMode::One {
+            self.raise_queue_changed();
+        }
+    }
+
+    fn remember(&mut self, item: &QueueItem<S>) {
+        self.history.push_back(PlayedItem {
+            track: item.track.clone(),
+            album_id: item.album_id,
+            source_factory: Arc::clone(&item.source_factory),
+        });
+        if self.history.len() > HISTORY_ENTRIES {
+            self.history.pop_front();
+        }
+    }
+
+    /// Restarts the current track if it's been playing for a few seconds, or otherwise puts the
+    /// last track played back at the front of the queue.
+    pub fn skip_to_previous(&mut self) -> Try<()> {
+        let position_secs = self.current_track().map_or(0.0, |t| t.position_secs);
+        if position_secs > RESTART_AFTER_SECS || self.history.is_empty() {
+            if !self.tracks.is_empty() {
+                self.seek(0.0)?;
+                self.raise_track_changed();
+            }
+            return Ok(());
+        }
+        let previous = self.history.pop_back().unwrap();
+        // the current track will start from the beginning again once the previous one is over
+        if let Some(current) = self.tracks.pop_front() {
+            self.tracks.push_front(current.restarted());
+        }
+        // it gets a new entry marker, since it may still be further down the queue too
+        let audio_source = CountedSource::new(Preroll::new(open_source(&previous.source_factory)));
+        let item = QueueItem {
+            track: EnqueuedTrack {
+                entry_marker: self.new_entry_marker(),
+                ..previous.track
+            },
+            album_id: previous.album_id,
+            source_factory: previous.source_factory,
+            audio_source,
+        };
+        self.tracks.push_front(item);
+        self.preroll();
         self.raise_track_changed();
-        popped
+        self.raise_queue_changed();
+        Ok(())
+    }
+
+    /// Returns the tracks which have been played, most recent first.
+    pub fn history(&self) -> impl Iterator<Item = &EnqueuedTrack> + '_ {
+        self.history.iter().rev().map(|t| &t.track)
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.unshuffled_order.is_some()
+    }
+
+    /// Puts the entries after the current one in a random order, or back in the order they were
+    /// in before that. Entries added while the queue was shuffled end up after the others.
+    pub fn set_shuffled(&mut self, shuffled: bool) {
+        if shuffled == self.is_shuffled() {
+            return;
+        }
+        let start = self.tracks.len().min(1);
+        let mut upcoming: Vec<QueueItem<S>> = self.tracks.drain(start..).collect();
+        if shuffled {
+            self.unshuffled_order = Some(
+                self.tracks
+                    .iter()
+                    .chain(upcoming.iter())
+                    .map(|t| t.track.entry_marker)
+                    .collect(),
+            );
+            upcoming.shuffle(&mut rand::thread_rng());
+        } else if let Some(order) = self.unshuffled_order.take() {
+            let positions: HashMap<EntryMarker, usize> = order
+                .into_iter()
+                .enumerate()
+                .map(|(i, marker)| (marker, i))
+                .collect();
+            upcoming.sort_by_key(|t| {
+                positions
+                    .get(&t.track.entry_marker)
+                    .cloned()
+                    .unwrap_or(usize::max_value())
+            });
+        }
+        self.tracks.extend(upcoming);
+        self.preroll();
+        self.raise_queue_changed();
+    }
+
+    /// Starts decoding the entries at the front of the queue in the background, so the next track
+    /// is ready to take over as soon as the current one runs out.
+    fn preroll(&mut self) {
+        for t in self.tracks.iter_mut().take(PREROLL_ENTRIES) {
+            t.audio_source.inner.start();
+        }
     }
 
     fn raise_track_changed(&self) {
         self.callback.on_current_track_changed(self);
     }
 
+    fn raise_queue_changed(&self) {
+        self.callback.on_queue_changed(self);
+    }
+
+    fn position(&self, marker: EntryMarker) -> Option<usize> {
+        self.tracks
+            .iter()
+            .position(|t| t.track.entry_marker == marker)
+    }
+
     // returns true iff the item was in the queue
     pub fn remove(&mut self, marker: EntryMarker) -> bool {
-        let length_before = self.tracks.len();
-        self.tracks.retain(|t| t.track.entry_marker != marker);
-        match length_before - self.tracks.len() {
-            0 => false,
-            1 => true,
-            _ => panic!("more than one track with entry marker {:?}", marker),
+        let index = match self.position(marker) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.tracks.remove(index);
+        self.preroll();
+        if index == 0 {
+            self.raise_track_changed();
+        }
+        self.raise_queue_changed();
+        true
+    }
+
+    /// Moves an entry to the given index, or to the end if the index is past it. Returns true iff
+    /// the entry was in the queue.
+    pub fn move_entry(&mut self, marker: EntryMarker, index: usize) -> bool {
+        let from = match self.position(marker) {
+            Some(from) => from,
+            None => return false,
+        };
+        let entry = self.tracks.remove(from).unwrap();
+        let to = index.min(self.tracks.len());
+        self.tracks.insert(to, entry);
+        self.preroll();
+        if from != to && (from == 0 || to == 0) {
+            self.raise_track_changed();
         }
+        self.raise_queue_changed();
+        true
+    }
+
+    /// Skips every entry before the given one, so that it starts playing. Returns true iff the
+    /// entry was in the queue.
+    pub fn jump_to(&mut self, marker: EntryMarker) -> bool {
+        let index = match self.position(marker) {
+            Some(index) => index,
+            None => return false,
+        };
+        let skipped: Vec<QueueItem<S>> = self.tracks.drain(..index).collect();
+        // only the current track has been played, the others were never reached
+        if let Some(current) = skipped.first() {
+            self.remember(current);
+        }
+        // like a skipped track, the skipped entries stay in the rotation
+        if self.controls.repeat == RepeatMode::All {
+            for item in skipped {
+                self.tracks.push_back(item.restarted());
+            }
+        }
+        self.preroll();
+        if index > 0 {
+            self.raise_track_changed();
+        }
+        self.raise_queue_changed();
+        true
     }
 
     pub fn clear(&mut self) {
         self.tracks.clear();
+        self.raise_queue_changed();
     }
 
-    fn tracks(&mut self) -> impl Iterator<Item = &EnqueuedTrack> + '_ {
+    pub fn tracks(&self) -> impl Iterator<Item = &EnqueuedTrack> + '_ {
         self.tracks.iter().map(|t| &t.track)
     }
 
     pub fn current_track(&self) -> Option<CurrentTrack> {
         self.tracks.get(0).map(|t| CurrentTrack {
             track: t.track.clone(),
-            position_secs: t.audio_source.samples_played as f32
-                / self.audio_format.channels as f32
-                / self.audio_format.sample_rate.0 as f32,
+            position_secs: t.audio_source.samples_played as f32 / self.samples_per_sec(),
         })
     }
 
-    fn next_sample(&mut self) -> Option<S> {
-        if let Some(track) = self.tracks.get_mut(0) {
-            if let Some(sample) = track.audio_source.next() {
-                Some(sample)
-            } else {
-                // current source is over, advance to next
-                self.tracks.pop_front();
-                self.raise_track_changed();
+    fn samples_per_sec(&self) -> f32 {
+        self.audio_format.channels as f32 * self.audio_format.sample_rate.0 as f32
+    }
+
+    /// Returns how far through the crossfade into the next track we are, if we are in one at all.
+    fn crossfade_progress(&self) -> Option<f32> {
+        let fade_samples = (self.controls.crossfade_secs * self.samples_per_sec()) as u64;
+        if fade_samples == 0 || self.controls.repeat == RepeatMode::One {
+            return None;
+        }
+        let current = self.tracks.get(0)?;
+        let next = self.tracks.get(1)?;
+        if current.album_id.is_some() && current.album_id == next.album_id {
+            // don't smear consecutive tracks from the same album, which are probably gapless
+            return None;
+        }
+        let total_samples = (current.track.duration_secs * self.samples_per_sec()) as u64;
+        let remaining_samples = total_samples.saturating_sub(current.audio_source.samples_played);
+        if remaining_samples > fade_samples {
+            None
+        } else {
+            Some(1.0 - remaining_samples as f32 / fade_samples as f32)
+        }
+    }
+
+    fn next_sample(&mut self) -> Polled<S> {
+        if let Some(progress) = self.crossfade_progress() {
+            let (fade_out_gain, fade_in_gain) = self.controls.crossfade_curve.gains(progress);
+            let mut tracks = self.tracks.iter_mut();
+            let (current, next) = (tracks.next().unwrap(), tracks.next().unwrap());
+            match current.audio_source.poll() {
+                Polled::Sample(sample) => {
+                    let incoming = match next.audio_source.poll() {
+                        Polled::Sample(incoming) => incoming,
+                        Polled::Underrun | Polled::Finished => Sample::zero_value(),
+                    };
+                    return Polled::Sample(
+                        sample
+                            .amplify(fade_out_gain)
+                            .saturating_add(incoming.amplify(fade_in_gain)),
+                    );
+                }
+                Polled::Underrun => return Polled::Underrun,
+                Polled::Finished => {}
+            }
+        }
+        match self.tracks.get_mut(0).map(|t| t.audio_source.poll()) {
+            Some(Polled::Finished) => {
+                // current source is over, advance to next, which has already been prerolled
+                self.finish_current();
                 // recurse now that current_source is updated
                 self.next_sample()
             }
-        } else {
+            Some(polled) => polled,
             // no current source, play silence
-            Some(Sample::zero_value())
+            None => Polled::Sample(Sample::zero_value()),
         }
     }
 }
@@ -173,22 +561,55 @@ where
 
     fn next(&mut self) -> Option<S> {
         if self.controls.paused {
-            Some(Sample::zero_value())
-        } else if self.controls.muted {
-            self.next_sample();
+            return Some(Sample::zero_value());
+        }
+        let sample = match self.next_sample() {
+            Polled::Sample(sample) => sample,
+            // rather than hold up the audio thread, play silence until the decoder catches up
+            Polled::Underrun | Polled::Finished => Sample::zero_value(),
+        };
+        if self.controls.muted {
             Some(Sample::zero_value())
         } else {
-            self.next_sample()
-                .map(|sample| sample.amplify(self.controls.volume))
+            Some(sample.amplify(self.controls.volume))
         }
     }
 }
 
+/// Makes a one-off opener for a decoder thread out of a source factory.
+fn open_source<S: 'static>(source_factory: &Arc<SourceFactory<S>>) -> OpenSource<S> {
+    let source_factory = Arc::clone(source_factory);
+    Box::new(move || source_factory())
+}
+
 struct QueueItem<S> {
     track: EnqueuedTrack,
+    album_id: Option<LibraryId<Album>>,
+    source_factory: Arc<SourceFactory<S>>,
     audio_source: CountedSource<S>,
 }
 
+impl<S> QueueItem<S>
+where
+    S: Sample + Send + 'static,
+{
+    /// Returns this entry with a new decoder, so that it can be played again from the start.
+    fn restarted(self) -> Self {
+        let audio_source = CountedSource::new(Preroll::new(open_source(&self.source_factory)));
+        QueueItem {
+            audio_source,
+            ..self
+        }
+    }
+}
+
+/// A track which has been played, which can be enqueued again without loading it.
+struct PlayedItem<S> {
+    track: EnqueuedTrack,
+    album_id: Option<LibraryId<Album>>,
+    source_factory: Arc<SourceFactory<S>>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct EnqueuedTrack {
     pub id: Id<Track>,
@@ -196,16 +617,16 @@ pub struct EnqueuedTrack {
     pub entry_marker: EntryMarker,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct EntryMarker(#[serde(with = "string")] u64);
 
 struct CountedSource<S> {
     samples_played: u64,
-    inner: Box<dyn Source<Item = S> + Send>,
+    inner: Preroll<S>,
 }
 
 impl<S> CountedSource<S> {
-    fn new(source: Box<dyn Source<Item = S> + Send>) -> Self {
+    fn new(source: Preroll<S>) -> Self {
         CountedSource {
             samples_played: 0,
             inner: source,
@@ -213,14 +634,336 @@ impl<S> CountedSource<S> {
     }
 }
 
-impl<S> Iterator for CountedSource<S> {
-    type Item = S;
-
-    fn next(&mut self) -> Option<S> {
-        let sample = self.inner.next();
-        if sample.is_some() {
+impl<S> CountedSource<S>
+where
+    S: Sample + Send + 'static,
+{
+    fn poll(&mut self) -> Polled<S> {
+        let polled = self.inner.poll();
+        if let Polled::Sample(_) = polled {
             self.samples_played += 1;
         }
-        sample
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ids::LibraryId;
+    use cpal::{SampleFormat, SampleRate};
+    use rodio::buffer::SamplesBuffer;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    struct NoCallback;
+
+    impl QueueCallback<f32> for NoCallback {
+        fn on_current_track_changed(&self, _queue: &Queue<f32, Self>) {}
+        fn on_queue_changed(&self, _queue: &Queue<f32, Self>) {}
+    }
+
+    fn test_queue() -> Queue<f32, NoCallback> {
+        let format = Format {
+            channels: 1,
+            sample_rate: SampleRate(44100),
+            data_type: SampleFormat::F32,
+        };
+        Queue::new(1.0, format, NoCallback)
+    }
+
+    fn constant_source(value: f32, samples: usize) -> SourceFactory<f32> {
+        Box::new(move || -> Try<Box<dyn Source<Item = f32> + Send>> {
+            Ok(Box::new(SamplesBuffer::new(1, 44100, vec![value; samples])))
+        })
+    }
+
+    /// Plays the given number of samples, waiting for the decoders to catch up when they haven't.
+    fn play(queue: &mut Queue<f32, NoCallback>, samples: usize) -> Vec<f32> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut played = Vec::with_capacity(samples);
+        while played.len() < samples {
+            match queue.next_sample() {
+                Polled::Sample(sample) => played.push(sample),
+                Polled::Underrun | Polled::Finished => {
+                    assert!(Instant::now() < deadline, "decoder never caught up");
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+        played
+    }
+
+    #[test]
+    fn no_silence_between_consecutive_tracks() {
+        let mut queue = test_queue();
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(1)),
+                0.1,
+                None,
+                constant_source(0.25, 4410),
+            )
+            .unwrap();
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(2)),
+                0.1,
+                None,
+                constant_source(0.5, 4410),
+            )
+            .unwrap();
+        let played = play(&mut queue, 2 * 4410);
+        assert!(played[..4410].iter().all(|&s| s == 0.25));
+        assert!(played[4410..].iter().all(|&s| s == 0.5));
+        // both tracks are over, so now we get silence
+        assert_eq!(queue.next(), Some(0.0));
+        assert!(queue.current_track().is_none());
+    }
+
+    #[test]
+    fn seek_restarts_decoding_from_the_new_position() {
+        let mut queue = test_queue();
+        let ramp: SourceFactory<f32> = Box::new(|| -> Try<Box<dyn Source<Item = f32> + Send>> {
+            let samples: Vec<f32> = (0..4410).map(|i| i as f32).collect();
+            Ok(Box::new(SamplesBuffer::new(1, 44100, samples)))
+        });
+        queue
+            .enqueue_last(Id::Library(LibraryId::new(1)), 0.1, None, ramp)
+            .unwrap();
+        assert_eq!(play(&mut queue, 1), vec![0.0]);
+        queue.seek(0.05).unwrap();
+        assert_eq!(queue.current_track().unwrap().position_secs, 0.05);
+        assert_eq!(play(&mut queue, 2), vec![2205.0, 2206.0]);
+    }
+
+    #[test]
+    fn crossfade_mixes_the_end_of_one_track_into_the_next() {
+        let mut queue = test_queue();
+        queue.controls.crossfade_secs = 0.05;
+        queue.controls.crossfade_curve = CrossfadeCurve::Linear;
+        let album = |id| Some(LibraryId::new(id));
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(1)),
+                0.1,
+                album(1),
+                constant_source(0.25, 4410),
+            )
+            .unwrap();
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(2)),
+                0.1,
+                album(2),
+                constant_source(0.5, 4410),
+            )
+            .unwrap();
+        let played = play(&mut queue, 4410 + 2205);
+        assert!(played[..2205].iter().all(|&s| s == 0.25));
+        assert!(played[2205..4410].iter().all(|&s| s > 0.2 && s < 0.55));
+        assert!(played[4410..].iter().all(|&s| s == 0.5));
+        assert_eq!(queue.next(), Some(0.0));
+    }
+
+    #[test]
+    fn silence_is_played_until_the_decoder_catches_up() {
+        let mut queue = test_queue();
+        let slow: SourceFactory<f32> = Box::new(|| -> Try<Box<dyn Source<Item = f32> + Send>> {
+            thread::sleep(Duration::from_millis(200));
+            Ok(Box::new(SamplesBuffer::new(1, 44100, vec![0.25; 4410])))
+        });
+        queue
+            .enqueue_last(Id::Library(LibraryId::new(1)), 0.1, None, slow)
+            .unwrap();
+        let started = Instant::now();
+        assert_eq!(queue.next(), Some(0.0));
+        assert!(started.elapsed() < Duration::from_millis(100));
+        assert_eq!(queue.current_track().unwrap().position_secs, 0.0);
+        assert_eq!(play(&mut queue, 1), vec![0.25]);
+    }
+
+    fn entry_ids(queue: &Queue<f32, NoCallback>) -> Vec<String> {
+        queue.tracks().map(|t| t.id.to_string()).collect()
+    }
+
+    #[test]
+    fn entries_can_be_moved_removed_and_jumped_to() {
+        let mut queue = test_queue();
+        let markers: Vec<EntryMarker> = (1..=4)
+            .map(|id| {
+                queue
+                    .enqueue_last(
+                        Id::Library(LibraryId::new(id)),
+                        0.1,
+                        None,
+                        constant_source(0.25, 4410),
+                    )
+                    .unwrap()
+            })
+            .collect();
+        assert!(queue.move_entry(markers[0], 2));
+        assert_eq!(entry_ids(&queue), vec!["2", "3", "1", "4"]);
+        assert!(queue.move_entry(markers[3], 100));
+        assert_eq!(entry_ids(&queue), vec!["2", "3", "1", "4"]);
+        assert!(queue.remove(markers[2]));
+        assert_eq!(entry_ids(&queue), vec!["2", "1", "4"]);
+        assert!(queue.jump_to(markers[0]));
+        assert_eq!(entry_ids(&queue), vec!["1", "4"]);
+        assert!(!queue.jump_to(markers[1]));
+        assert!(!queue.remove(markers[1]));
+        assert!(!queue.move_entry(markers[1], 0));
+    }
+
+    fn new_entries(ids: &[i64]) -> Vec<NewEntry<f32>> {
+        ids.iter()
+            .map(|&id| NewEntry {
+                id: Id::Library(LibraryId::new(id)),
+                duration_secs: 0.1,
+                album_id: None,
+                source: constant_source(0.25, 4410),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tracks_are_enqueued_together() {
+        let mut queue = test_queue();
+        queue
+            .enqueue_all(new_entries(&[1, 2]), EnqueueMode::Last)
+            .unwrap();
+        queue
+            .enqueue_all(new_entries(&[3, 4]), EnqueueMode::Next)
+            .unwrap();
+        assert_eq!(entry_ids(&queue), vec!["1", "3", "4", "2"]);
+        queue
+            .enqueue_all(new_entries(&[5]), EnqueueMode::Last)
+            .unwrap();
+        assert_eq!(entry_ids(&queue), vec!["1", "3", "4", "2", "5"]);
+        queue
+            .enqueue_all(new_entries(&[6, 7]), EnqueueMode::Replace)
+            .unwrap();
+        assert_eq!(entry_ids(&queue), vec!["6", "7"]);
+    }
+
+    #[test]
+    fn repeat_modes_keep_played_tracks() {
+        let mut queue = test_queue();
+        queue.controls.repeat = RepeatMode::All;
+        for &(id, value) in &[(1, 0.25), (2, 0.5)] {
+            queue
+                .enqueue_last(
+                    Id::Library(LibraryId::new(id)),
+                    0.01,
+                    None,
+                    constant_source(value, 441),
+                )
+                .unwrap();
+        }
+        let played = play(&mut queue, 3 * 441);
+        assert!(played[882..].iter().all(|&s| s == 0.25));
+        assert_eq!(entry_ids(&queue), vec!["1", "2"]);
+        queue.controls.repeat = RepeatMode::One;
+        let played = play(&mut queue, 2 * 441);
+        assert!(played.iter().all(|&s| s == 0.25));
+        assert_eq!(entry_ids(&queue), vec!["1", "2"]);
+        queue.skip_current();
+        queue.controls.repeat = RepeatMode::Off;
+        let played = play(&mut queue, 441);
+        assert!(played.iter().all(|&s| s == 0.5));
+        assert_eq!(entry_ids(&queue), vec!["2"]);
+    }
+
+    #[test]
+    fn jumping_keeps_skipped_tracks_when_repeating_all() {
+        let mut queue = test_queue();
+        queue.controls.repeat = RepeatMode::All;
+        let markers = queue
+            .enqueue_all(new_entries(&[1, 2, 3, 4]), EnqueueMode::Last)
+            .unwrap();
+        assert!(queue.jump_to(markers[2]));
+        assert_eq!(entry_ids(&queue), vec!["3", "4", "1", "2"]);
+    }
+
+    #[test]
+    fn shuffled_queue_can_be_put_back_in_order() {
+        let mut queue = test_queue();
+        let ids: Vec<i64> = (1..=20).collect();
+        queue
+            .enqueue_all(new_entries(&ids), EnqueueMode::Last)
+            .unwrap();
+        let in_order = entry_ids(&queue);
+        queue.set_shuffled(true);
+        assert!(queue.is_shuffled());
+        let mut shuffled = entry_ids(&queue);
+        assert_eq!(shuffled[0], "1");
+        shuffled.sort_by_key(|id| id.parse::<i64>().unwrap());
+        assert_eq!(shuffled, in_order);
+        queue
+            .enqueue_all(new_entries(&[21]), EnqueueMode::Next)
+            .unwrap();
+        queue.set_shuffled(false);
+        let mut expected = in_order;
+        expected.push("21".to_string());
+        assert_eq!(entry_ids(&queue), expected);
+    }
+
+    #[test]
+    fn previous_track_is_played_again() {
+        let mut queue = test_queue();
+        queue
+            .enqueue_all(new_entries(&[1, 2]), EnqueueMode::Last)
+            .unwrap();
+        play(&mut queue, 4410 + 1);
+        let history: Vec<String> = queue.history().map(|t| t.id.to_string()).collect();
+        assert_eq!(history, vec!["1"]);
+        queue.skip_to_previous().unwrap();
+        assert_eq!(entry_ids(&queue), vec!["1", "2"]);
+        assert_eq!(queue.history().count(), 0);
+        // with nothing to go back to, the current track starts again
+        play(&mut queue, 10);
+        queue.skip_to_previous().unwrap();
+        assert_eq!(entry_ids(&queue), vec!["1", "2"]);
+        assert_eq!(queue.current_track().unwrap().position_secs, 0.0);
+    }
+
+    #[test]
+    fn previous_track_after_jumping_is_the_one_jumped_from() {
+        let mut queue = test_queue();
+        let markers = queue
+            .enqueue_all(new_entries(&[1, 2, 3]), EnqueueMode::Last)
+            .unwrap();
+        play(&mut queue, 10);
+        assert!(queue.jump_to(markers[2]));
+        let history: Vec<String> = queue.history().map(|t| t.id.to_string()).collect();
+        assert_eq!(history, vec!["1"]);
+        queue.skip_to_previous().unwrap();
+        assert_eq!(entry_ids(&queue), vec!["1", "3"]);
+    }
+
+    #[test]
+    fn no_crossfade_within_an_album() {
+        let mut queue = test_queue();
+        queue.controls.crossfade_secs = 0.05;
+        let album = Some(LibraryId::new(1));
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(1)),
+                0.1,
+                album,
+                constant_source(0.25, 4410),
+            )
+            .unwrap();
+        queue
+            .enqueue_last(
+                Id::Library(LibraryId::new(2)),
+                0.1,
+                album,
+                constant_source(0.5, 4410),
+            )
+            .unwrap();
+        let played = play(&mut queue, 2 * 4410);
+        assert!(played[..4410].iter().all(|&s| s == 0.25));
+        assert!(played[4410..].iter().all(|&s| s == 0.5));
     }
 }
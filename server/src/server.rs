@@ -1,27 +1,42 @@
 use crate::api::{App, Request};
 use crate::api::{EventSink, Payload};
+use crate::art::ArtCache;
 use crate::bootstrap::bootstrap_library;
+use crate::config::Config;
 use crate::errors::Try;
 use crate::http;
 use crate::library::Library;
 use crate::player::PlayerApp;
 use crate::services::{Service, ServiceId};
+use crate::watcher::watch_library_roots;
 use crate::websocket::ws_connection;
 use log;
 use parking_lot::Mutex;
 use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
 use std::sync::Arc;
 use warp::Filter;
 
 pub struct Server {
-    services: HashMap<ServiceId, Box<dyn Service>>,
+    config: Config,
+    services: HashMap<ServiceId, Arc<dyn Service>>,
 }
 
 impl Server {
-    pub fn new(services: Vec<Box<dyn Service>>) -> Self {
-        Server {
-            services: services.into_iter().map(|s| (s.id(), s)).collect(),
+    /// Creates a server which will register those of the given services that the config enables.
+    pub fn new(config: Config, services: Vec<Box<dyn Service>>) -> Self {
+        let services: HashMap<ServiceId, Arc<dyn Service>> = services
+            .into_iter()
+            .filter(|s| config.service_enabled(&s.id().0))
+            .map(|s| (s.id(), Arc::from(s)))
+            .collect();
+        for service_id in config.services.iter().flatten() {
+            if !services.contains_key(&ServiceId(service_id.clone())) {
+                log::warn!("service {} is enabled but not available", service_id);
+            }
         }
+        Server { config, services }
     }
 
     pub fn run(self) -> Try<()> {
@@ -29,15 +44,22 @@ impl Server {
         event_sink.add_destination(Box::new(|payload: &Payload| {
             log::info!("event: {}", payload.json)
         }));
-        let player_app = PlayerApp::new(Arc::clone(&event_sink))?;
-        let database_path = format!(
-            "database-{}.sqlite",
-            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
-        );
-        log::info!("opening database file {}", database_path);
-        let library = Library::new(database_path, Arc::clone(&event_sink))?;
-        if let Err(e) = bootstrap_library(&library) {
-            log::warn!("Did not bootstrap library: {}", e)
+        let player_app = PlayerApp::new(
+            Arc::clone(&event_sink),
+            &self.config.audio_output,
+            self.config.initial_volume,
+        )?;
+        log::info!("opening database file {}", self.config.database_path);
+        let art_cache = Arc::new(ArtCache::new(&self.config.art_cache_path)?);
+        let library = Library::new(
+            self.config.database_path,
+            Arc::clone(&event_sink),
+            Some(Arc::clone(&art_cache)),
+        )?;
+        if library.is_empty()? {
+            if let Err(e) = bootstrap_library(&library, &self.config.bootstrap_path) {
+                log::warn!("Did not bootstrap library: {}", e)
+            }
         }
         let app = Arc::new(App {
             services: self.services,
@@ -45,6 +67,7 @@ impl Server {
             library,
             event_sink: Arc::clone(&event_sink),
         });
+        watch_library_roots(Arc::clone(&app), self.config.library_roots.clone())?;
 
         let app_state = warp::any().map(move || app.clone());
 
@@ -55,6 +78,25 @@ impl Server {
             .and(app_state.clone())
             .map(|request: Request, app: Arc<App>| http::api_handler(app, request));
 
+        let art_cache = warp::any().map(move || Arc::clone(&art_cache));
+
+        let art = warp::get2()
+            .and(warp::path("art"))
+            .and(warp::path::param())
+            .and(warp::path::end())
+            .and(art_cache.clone())
+            .map(|hash: String, art_cache: Arc<ArtCache>| http::art_handler(&art_cache, &hash));
+
+        let thumbnail = warp::get2()
+            .and(warp::path("art"))
+            .and(warp::path::param())
+            .and(warp::path("thumbnail"))
+            .and(warp::path::end())
+            .and(art_cache)
+            .map(|hash: String, art_cache: Arc<ArtCache>| {
+                http::thumbnail_handler(&art_cache, &hash)
+            });
+
         let websocket = warp::get2()
             .and(warp::path("ws"))
             .and(warp::path::end())
@@ -64,8 +106,68 @@ impl Server {
                 ws.on_upgrade(move |ws| ws_connection(app, ws))
             });
 
-        warp::serve(http_rpc.or(websocket)).run(([127, 0, 0, 1], 8080));
+        let address = self.config.socket_address();
+        log::info!("listening on {}", address);
+        warp::serve(http_rpc.or(art).or(thumbnail).or(websocket)).run(address);
 
         Ok(())
     }
 }
+
+/// Copies the library from an old database file, such as the timestamped ones we used to create on
+/// every run, into the database at `database_path`.
+pub fn import_database(database_path: String, old_database_path: String) -> Try<()> {
+    // sqlite would happily create an empty database for us to import nothing from
+    if !Path::new(&old_database_path).is_file() {
+        return Err(anyhow!(
+            "database file {} does not exist",
+            old_database_path
+        ));
+    }
+    let event_sink = Arc::new(EventSink::empty());
+    log::info!(
+        "importing database file {} into {}",
+        old_database_path,
+        database_path
+    );
+    // opening a library migrates it, so we open a copy rather than change the old file
+    let old_copy_dir = tempfile::tempdir()?;
+    let old_copy_path = old_copy_dir.path().join("old.sqlite");
+    fs::copy(&old_database_path, &old_copy_path)?;
+    let old_copy_path = old_copy_path
+        .to_str()
+        .ok_or_else(|| anyhow!("invalid temporary path {:?}", old_copy_path))?
+        .to_string();
+    // cover art URLs are copied as they are, so there's no need to read any art
+    let library = Library::new(database_path, Arc::clone(&event_sink), None)?;
+    let old_library = Library::new(old_copy_path, event_sink, None)?;
+    library.import(&old_library)
+}
+
+/// Splits up albums in the database at `database_path` which hold tracks from different albums,
+/// which older versions created for albums with the same title.
+pub fn repair_albums(database_path: String) -> Try<()> {
+    log::info!("repairing albums in {}", database_path);
+    let library = Library::new(database_path, Arc::new(EventSink::empty()), None)?;
+    let split = library.split_merged_albums()?;
+    log::info!("split up {} albums", split);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn importing_a_missing_database_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let database_path = dir.path().join("database.sqlite");
+        let old_database_path = dir.path().join("missing.sqlite");
+        let result = import_database(
+            database_path.to_str().unwrap().to_string(),
+            old_database_path.to_str().unwrap().to_string(),
+        );
+        assert!(result.is_err());
+        assert!(!old_database_path.exists());
+    }
+}
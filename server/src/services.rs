@@ -9,6 +9,7 @@ pub struct ServiceId(pub String);
 pub trait Service: Send + Sync {
     fn id(&self) -> ServiceId;
     fn search(&self, query: &str) -> Try<SearchResults>;
+    /// Returns a track which streams its data when it's opened, rather than downloading it now.
     fn fetch(&self, track_id: &IdString<Track>) -> Try<LoadedTrack>;
     fn track_info(&self, track_id: &IdString<Track>) -> Try<ExternalTrack>;
 }
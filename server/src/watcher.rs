@@ -0,0 +1,272 @@
+use crate::api::{App, EventSink};
+use crate::errors::Try;
+use crate::file_formats;
+use crate::library::{scan_directory, Library};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long file events are held back for, so that a file being written produces a single change.
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(2);
+
+/// Keeps the library in sync with the files under the given folders: new files are imported,
+/// changed files are read again, moved files are followed, and deleted files are marked missing.
+pub fn watch_library_roots(app: Arc<App>, roots: Vec<String>) -> Try<()> {
+    if roots.is_empty() {
+        return Ok(());
+    }
+    // the library stores the paths we scan with, and notify reports absolute paths, so they have to
+    // be spelled the same way
+    let roots = roots
+        .iter()
+        .map(|root| canonical_root(root))
+        .collect::<Try<Vec<String>>>()?;
+    let (events_tx, events_rx) = mpsc::channel();
+    let mut watcher: RecommendedWatcher = notify::watcher(events_tx, DEBOUNCE_DELAY)?;
+    for root in &roots {
+        log::info!("watching library root {}", root);
+        watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+    thread::Builder::new()
+        .name("library watcher".to_string())
+        .spawn(move || {
+            // the watcher stops sending events when it's dropped, so it lives as long as this thread
+            let _watcher = watcher;
+            if let Err(e) = sync_roots(&app.library, &app.event_sink, &roots) {
+                log::error!("failed to sync library roots: {:#}", e);
+            }
+            for event in events_rx {
+                if let Err(e) = handle_event(&app.library, &app.event_sink, &roots, event) {
+                    log::error!("failed to update library from file change: {:#}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn canonical_root(root: &str) -> Try<String> {
+    let path =
+        fs::canonicalize(root).map_err(|e| anyhow!("can't watch library root {}: {}", root, e))?;
+    Ok(path_str(&path)?.to_string())
+}
+
+/// Catches up with changes that happened while we weren't watching.
+fn sync_roots(library: &Library, event_sink: &EventSink, roots: &[String]) -> Try<()> {
+    for root in roots {
+        scan_directory(library, event_sink, root, true)?;
+        for (track_id, file_path) in library.find_local_tracks_under(root)? {
+            let missing = !Path::new(&file_path).exists();
+            library.set_track_missing(track_id, missing)?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_event(
+    library: &Library,
+    event_sink: &EventSink,
+    roots: &[String],
+    event: DebouncedEvent,
+) -> Try<()> {
+    match event {
+        DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+            file_changed(library, event_sink, &path)
+        }
+        DebouncedEvent::Remove(path) => {
+            for (track_id, _) in library.find_local_tracks_under(path_str(&path)?)? {
+                library.set_track_missing(track_id, true)?;
+            }
+            Ok(())
+        }
+        DebouncedEvent::Rename(from, to) => {
+            let (from, to) = (path_str(&from)?, path_str(&to)?);
+            let moved = library.find_local_tracks_under(from)?;
+            if moved.is_empty() {
+                // probably a temporary file being renamed over the real one
+                return file_changed(library, event_sink, Path::new(to));
+            }
+            for (track_id, file_path) in moved {
+                let new_path = format!("{}{}", to, &file_path[from.len()..]);
+                library.move_local_track(track_id, new_path)?;
+            }
+            Ok(())
+        }
+        DebouncedEvent::Rescan => sync_roots(library, event_sink, roots),
+        DebouncedEvent::Error(e, path) => {
+            log::warn!("error watching {:?}: {}", path, e);
+            Ok(())
+        }
+        DebouncedEvent::NoticeWrite(_)
+        | DebouncedEvent::NoticeRemove(_)
+        | DebouncedEvent::Chmod(_) => Ok(()),
+    }
+}
+
+/// Imports a file that has appeared, or reads the tags of a known one again.
+fn file_changed(library: &Library, event_sink: &EventSink, path: &Path) -> Try<()> {
+    if path.is_dir() {
+        scan_directory(library, event_sink, path_str(path)?, true)?;
+        return Ok(());
+    }
+    if !file_formats::is_supported(path) {
+        return Ok(());
+    }
+    let file_path = path_str(path)?;
+    let metadata = file_formats::read_metadata(file_path.to_string())?;
+    match library.find_local_tracks_under(file_path)?.first() {
+        Some((track_id, _)) => library.update_local_track(*track_id, metadata),
+        None => {
+            let track_id = library.add_local_track_metadata(metadata)?;
+            log::info!("imported new file {} as track {}", file_path, track_id);
+            Ok(())
+        }
+    }
+}
+
+fn path_str(path: &Path) -> Try<&str> {
+    path.to_str()
+        .ok_or_else(|| anyhow!("invalid file name {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/src/file_formats/fixtures/tagged.m4a"
+    );
+
+    /// A new library in a temporary directory, with a music folder next to it.
+    struct TestLibrary {
+        directory: tempfile::TempDir,
+        library: Library,
+        event_sink: EventSink,
+    }
+
+    impl TestLibrary {
+        fn new() -> Self {
+            let directory = tempfile::tempdir().unwrap();
+            let database_path = directory.path().join("library.sqlite");
+            let library = Library::new(
+                database_path.to_str().unwrap().to_string(),
+                Arc::new(EventSink::empty()),
+                None,
+            )
+            .unwrap();
+            fs::create_dir(directory.path().join("music")).unwrap();
+            TestLibrary {
+                directory,
+                library,
+                event_sink: EventSink::empty(),
+            }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.directory.path().join("music").join(name)
+        }
+
+        fn handle(&self, event: DebouncedEvent) {
+            let roots = vec![path_str(&self.path("")).unwrap().to_string()];
+            handle_event(&self.library, &self.event_sink, &roots, event).unwrap();
+        }
+
+        /// The file path, title and missing flag of every track in the library.
+        fn tracks(&self) -> Vec<(String, String, bool)> {
+            self.library
+                .tracks()
+                .unwrap()
+                .map(|t| {
+                    let file_path = t.track_info.file_path.unwrap();
+                    (file_path, t.track_info.title, t.missing)
+                })
+                .collect()
+        }
+    }
+
+    fn path_string(path: &Path) -> String {
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn created_files_are_imported() {
+        let test = TestLibrary::new();
+        let path = test.path("song.m4a");
+        fs::copy(FIXTURE, &path).unwrap();
+        test.handle(DebouncedEvent::Create(path.clone()));
+        assert_eq!(
+            test.tracks(),
+            vec![(path_string(&path), "Song Title".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn written_files_are_read_again() {
+        let test = TestLibrary::new();
+        let path = test.path("song.m4a");
+        fs::copy(FIXTURE, &path).unwrap();
+        test.handle(DebouncedEvent::Create(path.clone()));
+
+        // retag the file with a title of the same length
+        let mut data = fs::read(&path).unwrap();
+        let title = data.windows(10).position(|w| w == b"Song Title").unwrap();
+        data[title..title + 10].copy_from_slice(b"New Title!");
+        fs::write(&path, data).unwrap();
+        test.handle(DebouncedEvent::Write(path.clone()));
+
+        assert_eq!(
+            test.tracks(),
+            vec![(path_string(&path), "New Title!".to_string(), false)]
+        );
+    }
+
+    #[test]
+    fn removed_files_are_marked_missing() {
+        let test = TestLibrary::new();
+        let path = test.path("song.m4a");
+        fs::copy(FIXTURE, &path).unwrap();
+        test.handle(DebouncedEvent::Create(path.clone()));
+        fs::remove_file(&path).unwrap();
+        test.handle(DebouncedEvent::Remove(path.clone()));
+        assert_eq!(
+            test.tracks(),
+            vec![(path_string(&path), "Song Title".to_string(), true)]
+        );
+    }
+
+    #[test]
+    fn renamed_files_and_directories_are_followed() {
+        let test = TestLibrary::new();
+        fs::create_dir(test.path("album")).unwrap();
+        let path = test.path("album/song.m4a");
+        fs::copy(FIXTURE, &path).unwrap();
+        test.handle(DebouncedEvent::Create(path.clone()));
+
+        let renamed = test.path("album/renamed.m4a");
+        fs::rename(&path, &renamed).unwrap();
+        test.handle(DebouncedEvent::Rename(path, renamed.clone()));
+        assert_eq!(
+            test.tracks(),
+            vec![(path_string(&renamed), "Song Title".to_string(), false)]
+        );
+
+        fs::rename(test.path("album"), test.path("moved")).unwrap();
+        test.handle(DebouncedEvent::Rename(
+            test.path("album"),
+            test.path("moved"),
+        ));
+        assert_eq!(
+            test.tracks(),
+            vec![(
+                path_string(&test.path("moved/renamed.m4a")),
+                "Song Title".to_string(),
+                false
+            )]
+        );
+    }
+}